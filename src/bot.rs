@@ -18,9 +18,12 @@ use tokio::sync::Mutex;
 
 use crate::{
     channels::{self, DynTx, DynamicTx},
-    db::{Model, User},
+    db::{DeferredMessage, Model, User},
     notifications::{NotificationEvents, UpdateEvents},
-    parsing::types::Class,
+    parsing::{
+        control::Controls,
+        types::{Class, ClassChange, Reservation},
+    },
     Config,
 };
 
@@ -40,6 +43,10 @@ pub struct BotState {
     pub config: &'static BotConfig,
     pub users_coll: Collection<User>,
     pub classes_coll: Collection<Class>,
+    pub reservations_coll: Collection<Reservation>,
+    pub changes_coll: Collection<ClassChange>,
+    pub deferred_coll: Collection<DeferredMessage>,
+    pub parser_controls: Controls,
     pub logger: Logger,
 }
 type DialogueStorage<State> = teloxide::dispatching::dialogue::InMemStorage<State>;
@@ -100,28 +107,52 @@ fn setup_sender(state: &Arc<BotState>, notification_rx: impl channels::Rx<Notifi
     notifications_sender(Arc::downgrade(state), notification_rx);
 }
 
-pub fn setup_bot(
+pub fn new_bot(config: &BotConfig) -> OurBot {
+    Bot::new(config.bot_token.clone()).parse_mode(ParseMode::Html)
+}
+
+/// State shared by handlers, also enough to send notifications
+/// without dispatching updates, e.g. from command line tools
+pub fn setup_state(
     config: &'static Config,
     logger: &Logger,
     db: &mongodb::Database,
-    notification_rx: impl channels::Rx<NotificationEvents>,
+    bot: OurBot,
     update_tx: DynamicTx<UpdateEvents>,
-) -> Dispatcher<OurBot, eyre::Report, DefaultKey> {
+    parser_controls: Controls,
+) -> Arc<BotState> {
     let users_coll = db.collection(&User::COLLECTION_NAME);
     let classes_coll = db.collection(&Class::COLLECTION_NAME);
+    let reservations_coll = db.collection(Reservation::COLLECTION_NAME);
+    let changes_coll = db.collection(ClassChange::COLLECTION_NAME);
+    let deferred_coll = db.collection(DeferredMessage::COLLECTION_NAME);
 
     let logger = logger.new(slog::o!("subsystem" => "bot"));
 
-    let bot = Bot::new(config.telegram.bot_token.clone()).parse_mode(ParseMode::Html);
-
-    let state = Arc::new(BotState {
-        bot: Mutex::new(bot.clone()),
+    Arc::new(BotState {
+        bot: Mutex::new(bot),
         config: &config.telegram,
         users_coll,
         classes_coll,
+        reservations_coll,
+        changes_coll,
+        deferred_coll,
+        parser_controls,
         update_tx,
         logger,
-    });
+    })
+}
+
+pub fn setup_bot(
+    config: &'static Config,
+    logger: &Logger,
+    db: &mongodb::Database,
+    notification_rx: impl channels::Rx<NotificationEvents>,
+    update_tx: DynamicTx<UpdateEvents>,
+    parser_controls: Controls,
+) -> Dispatcher<OurBot, eyre::Report, DefaultKey> {
+    let bot = new_bot(&config.telegram);
+    let state = setup_state(config, logger, db, bot.clone(), update_tx, parser_controls);
 
     setup_sender(&state, notification_rx);
 
@@ -148,19 +179,43 @@ pub mod commands {
     #[command(rename_rule = "snake_case")]
     pub enum UserCommands {
         Start,
+        /// where the lecturer has class now or later today
+        Lecturer(String),
+        /// what changed in classes of user's groups, for the last 7 days by default
+        Changes(String),
+        /// show or set reminders before classes, e.g. "15m 1h30m 1d" or "off"
+        Reminders(String),
+        /// show or set time of the daily list of classes, e.g. "07:30" or "off"
+        Digest(String),
+        /// show or set time of the Sunday preview of the coming week, e.g. "19:00" or "off"
+        Weekly(String),
+        /// show or set hours notifications are held back in, e.g. "23:00-07:00" or "off"
+        Quiet(String),
+        /// parser status, pause, resume and reparse, only for admins
+        Parser(String),
     }
 
     pub fn handler() -> super::BotHandler {
         Update::filter_message()
             .filter_command::<UserCommands>()
             .branch(dptree::case![UserCommands::Start].endpoint(gui::main_menu))
+            .branch(dptree::case![UserCommands::Lecturer(name)].endpoint(gui::lecturer_location))
+            .branch(dptree::case![UserCommands::Changes(days)].endpoint(gui::recent_changes))
+            .branch(dptree::case![UserCommands::Reminders(args)].endpoint(gui::reminders))
+            .branch(dptree::case![UserCommands::Digest(args)].endpoint(gui::digest_setting))
+            .branch(dptree::case![UserCommands::Weekly(args)].endpoint(gui::weekly_digest_setting))
+            .branch(dptree::case![UserCommands::Quiet(args)].endpoint(gui::quiet_hours_setting))
+            .branch(
+                dptree::case![UserCommands::Parser(args)]
+                    .endpoint(gui::parser_control::parser_control),
+            )
     }
 }
 
 pub mod notifications_sender {
     use std::{collections::HashSet, sync::Weak};
 
-    use chrono::{Datelike, Utc};
+    use chrono::{DateTime, Datelike, NaiveDate, Utc};
     use eyre::bail;
     use slog::Logger;
     use teloxide::{
@@ -168,10 +223,20 @@ pub mod notifications_sender {
         types::ParseMode, Bot,
     };
 
-    use super::{common::formatters::format_class_long, BotState, OurBot};
-    use crate::{channels, db::UserID, notifications::NotificationEvents, parsing::types::Class};
+    use super::{
+        common::formatters::{format_class_changes, format_class_long, format_reservation_long},
+        BotState, OurBot,
+    };
+    use crate::{
+        channels,
+        db::{DeferredMessage, User, UserID},
+        notifications::{NotificationEvent, NotificationEvents},
+        parsing::types::{Class, ClassField, Reservation},
+    };
 
     const RESEND_ATTEMPTS: usize = 10;
+    /// how often messages held back by quiet hours are checked
+    const DEFERRED_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
 
     async fn send_message_safe(
         bot: &OurBot,
@@ -200,6 +265,47 @@ pub mod notifications_sender {
         bail!("resend attempts reached")
     }
 
+    /// End of user's quiet hours if `at` falls into them
+    fn quiet_until(user: &User, at: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        user.quiet_hours
+            .and_then(|quiet_hours| quiet_hours.end_after(at))
+    }
+
+    /// Sends the message, or keeps it until user's quiet hours are over
+    async fn deliver(state: &BotState, user: &User, content: String) -> eyre::Result<()> {
+        if let Some(send_at) = quiet_until(user, Utc::now()) {
+            slog::info!(state.logger, "notifications.deliver.deferred"; "id" => ?user.telegram_id, "until" => %send_at);
+
+            let message = DeferredMessage {
+                user_id: user.telegram_id,
+                content,
+                send_at,
+            };
+            state.deferred_coll.insert_one(message).await?;
+            return Ok(());
+        }
+
+        let bot = state.bot.lock().await;
+        send_message_safe(&bot, user.telegram_id, &state.logger, content).await
+    }
+
+    async fn send_deferred(state: &BotState) -> eyre::Result<()> {
+        loop {
+            let Some(message) = state
+                .deferred_coll
+                .find_one_and_delete(
+                    mongodb::bson::doc! {"send_at": {"$lte": bson::DateTime::now()}},
+                )
+                .await?
+            else {
+                return Ok(());
+            };
+
+            let bot = state.bot.lock().await;
+            send_message_safe(&bot, message.user_id, &state.logger, message.content).await?;
+        }
+    }
+
     async fn handle_scheduled(state: &BotState, class: Class, user: UserID) -> eyre::Result<()> {
         let Some(user) = state
             .users_coll
@@ -210,7 +316,15 @@ pub mod notifications_sender {
             return Ok(());
         };
 
-        let in_minutes = (class.range.start - Utc::now()).num_minutes();
+        // reminder is told once quiet hours are over, unless it's too late by then
+        let now = Utc::now();
+        let send_at = quiet_until(&user, now).unwrap_or(now);
+        if send_at >= class.range.start {
+            slog::info!(state.logger, "notifications.handle_scheduled.stale"; "id" => ?user.telegram_id);
+            return Ok(());
+        }
+
+        let in_minutes = (class.range.start - send_at).num_minutes();
 
         let content = format_class_long(&class, &user.language);
         let content = t!(
@@ -221,12 +335,56 @@ pub mod notifications_sender {
         )
         .to_string();
 
-        let bot = state.bot.lock().await;
-
-        send_message_safe(&bot, user.telegram_id, &state.logger, content).await?;
+        deliver(state, &user, content).await?;
 
         Ok(())
     }
+    async fn handle_digest(state: &BotState, user: UserID) -> eyre::Result<()> {
+        let Some(user) = state
+            .users_coll
+            .find_one(mongodb::bson::doc! {"id": &user.0})
+            .await?
+        else {
+            slog::error!(state.logger, "notifications.handle_digest.user_not_found"; "id" => ?user);
+            return Ok(());
+        };
+
+        // nothing to tell on days off
+        let Some(content) = super::gui::format_daily_digest(state, &user).await? else {
+            return Ok(());
+        };
+
+        deliver(state, &user, content).await
+    }
+
+    async fn handle_weekly_digest(state: &BotState, user: UserID) -> eyre::Result<()> {
+        let Some(user) = state
+            .users_coll
+            .find_one(mongodb::bson::doc! {"id": &user.0})
+            .await?
+        else {
+            slog::error!(state.logger, "notifications.handle_weekly_digest.user_not_found"; "id" => ?user);
+            return Ok(());
+        };
+
+        let content = super::gui::format_weekly_digest(state, &user).await?;
+
+        // free week still counts, changes are highlighted since this moment
+        state
+            .users_coll
+            .update_one(
+                mongodb::bson::doc! {"id": &user.telegram_id.0},
+                mongodb::bson::doc! {"$set": {"last_weekly_digest": bson::DateTime::now()}},
+            )
+            .await?;
+
+        let Some(content) = content else {
+            return Ok(());
+        };
+
+        deliver(state, &user, content).await
+    }
+
     async fn handle_deleted(
         state: &BotState,
         class: Class,
@@ -250,9 +408,157 @@ pub mod notifications_sender {
             )
             .to_string();
 
-            let bot = state.bot.lock().await;
+            deliver(state, &user, content).await?;
+        }
+
+        Ok(())
+    }
 
-            send_message_safe(&bot, user.telegram_id, &state.logger, content).await?;
+    async fn handle_added(
+        state: &BotState,
+        class: Class,
+        users: HashSet<UserID>,
+    ) -> eyre::Result<()> {
+        for user in users {
+            let Some(user) = state
+                .users_coll
+                .find_one(mongodb::bson::doc! {"id": &user.0})
+                .await?
+            else {
+                slog::error!(state.logger, "notifications.handle_added.user_not_found"; "id" => ?user);
+                continue;
+            };
+
+            let content = t!(
+                "notifications.class.added",
+                locale = user.language.code(),
+                content = format_class_long(&class, &user.language)
+            )
+            .to_string();
+
+            deliver(state, &user, content).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn handle_modified(
+        state: &BotState,
+        class: Class,
+        diff: Vec<ClassField>,
+        users: HashSet<UserID>,
+    ) -> eyre::Result<()> {
+        for user in users {
+            let Some(user) = state
+                .users_coll
+                .find_one(mongodb::bson::doc! {"id": &user.0})
+                .await?
+            else {
+                slog::error!(state.logger, "notifications.handle_modified.user_not_found"; "id" => ?user);
+                continue;
+            };
+
+            let content = t!(
+                "notifications.class.modified",
+                locale = user.language.code(),
+                changes = format_class_changes(&class, &diff, &user.language),
+                content = format_class_long(&class, &user.language)
+            )
+            .to_string();
+
+            deliver(state, &user, content).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn handle_reservation(
+        state: &BotState,
+        reservation: Reservation,
+        users: HashSet<UserID>,
+    ) -> eyre::Result<()> {
+        for user in users {
+            let Some(user) = state
+                .users_coll
+                .find_one(mongodb::bson::doc! {"id": &user.0})
+                .await?
+            else {
+                slog::error!(state.logger, "notifications.handle_reservation.user_not_found"; "id" => ?user);
+                continue;
+            };
+
+            let content = t!(
+                "notifications.reservation.announced",
+                locale = user.language.code(),
+                content = format_reservation_long(&reservation, &user.language)
+            )
+            .to_string();
+
+            deliver(state, &user, content).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn handle_parser_alert(
+        state: &BotState,
+        parser: &str,
+        failures: u32,
+        error: &str,
+        admins: HashSet<UserID>,
+    ) -> eyre::Result<()> {
+        for admin in admins {
+            let Some(admin) = state
+                .users_coll
+                .find_one(mongodb::bson::doc! {"id": &admin.0})
+                .await?
+            else {
+                slog::error!(state.logger, "notifications.handle_parser_alert.user_not_found"; "id" => ?admin);
+                continue;
+            };
+
+            let content = t!(
+                "notifications.parser.failing",
+                locale = admin.language.code(),
+                parser = parser,
+                failures = failures,
+                error = teloxide::utils::html::escape(error)
+            )
+            .to_string();
+
+            deliver(state, &admin, content).await?;
+        }
+
+        Ok(())
+    }
+
+    async fn handle_mass_removal_alert(
+        state: &BotState,
+        parser: &str,
+        day: NaiveDate,
+        removed: usize,
+        admins: HashSet<UserID>,
+    ) -> eyre::Result<()> {
+        for admin in admins {
+            let Some(admin) = state
+                .users_coll
+                .find_one(mongodb::bson::doc! {"id": &admin.0})
+                .await?
+            else {
+                slog::error!(state.logger, "notifications.handle_mass_removal_alert.user_not_found"; "id" => ?admin);
+                continue;
+            };
+
+            let content = t!(
+                "notifications.parser.mass_removal_held",
+                locale = admin.language.code(),
+                parser = parser,
+                day = day,
+                removed = removed
+            )
+            .to_string();
+
+            deliver(state, &admin, content).await?;
         }
 
         Ok(())
@@ -263,38 +569,89 @@ pub mod notifications_sender {
         notification_rx: impl channels::Rx<NotificationEvents>,
     ) -> tokio::task::JoinHandle<eyre::Result<()>> {
         let fut = async move {
+            let mut deferred = tokio::time::interval(DEFERRED_POLL_INTERVAL);
+
             loop {
-                let new_notifications = notification_rx.recv().await?;
+                tokio::select! {
+                    new_notifications = notification_rx.recv() => {
+                        let new_notifications = new_notifications?;
 
-                let Some(current_state) = state.upgrade() else {
-                    // meaning all the other modules have exited
-                    return Ok(());
-                };
-
-                for notification in new_notifications.into_iter() {
-                    match notification {
-                        crate::notifications::NotificationEvent::ClassDeleted {
-                            class,
-                            affected_users,
-                        } => handle_deleted(&current_state, class, affected_users).await?,
-                        crate::notifications::NotificationEvent::Scheduled { class, user_id } => {
-                            handle_scheduled(&current_state, class, user_id).await?;
+                        let Some(current_state) = state.upgrade() else {
+                            // meaning all the other modules have exited
+                            return Ok(());
+                        };
+
+                        for notification in new_notifications.into_iter() {
+                            send_notification(&current_state, notification).await?;
                         }
                     }
+                    _ = deferred.tick() => {
+                        let Some(current_state) = state.upgrade() else {
+                            return Ok(());
+                        };
+
+                        send_deferred(&current_state).await?;
+                    }
                 }
             }
         };
 
         tokio::spawn(fut)
     }
+
+    pub async fn send_notification(
+        state: &BotState,
+        notification: NotificationEvent,
+    ) -> eyre::Result<()> {
+        match notification {
+            NotificationEvent::ClassDeleted {
+                class,
+                affected_users,
+            } => handle_deleted(state, class, affected_users).await,
+            NotificationEvent::ClassModified {
+                class,
+                diff,
+                affected_users,
+            } => handle_modified(state, class, diff, affected_users).await,
+            NotificationEvent::ClassAdded {
+                class,
+                affected_users,
+            } => handle_added(state, class, affected_users).await,
+            NotificationEvent::ParserAlert {
+                parser,
+                failures,
+                error,
+                admins,
+            } => handle_parser_alert(state, parser, failures, &error, admins).await,
+            NotificationEvent::MassRemovalAlert {
+                parser,
+                day,
+                removed,
+                admins,
+            } => handle_mass_removal_alert(state, parser, day, removed, admins).await,
+            NotificationEvent::Scheduled { class, user_id } => {
+                handle_scheduled(state, class, user_id).await
+            }
+            NotificationEvent::DailyDigest { user_id } => handle_digest(state, user_id).await,
+            NotificationEvent::WeeklyDigest { user_id } => {
+                handle_weekly_digest(state, user_id).await
+            }
+            NotificationEvent::ReservationAnnounced {
+                reservation,
+                affected_users,
+            } => handle_reservation(state, reservation, affected_users).await,
+        }
+    }
 }
 
 pub mod common {
 
     pub mod formatters {
+        use std::collections::HashSet;
+
         use crate::{
-            db::Language,
-            parsing::types::{Class, ClassPlace},
+            db::{Language, NotificationConstraint, OffsetError},
+            parsing::types::{Class, ClassField, ClassKind, ClassPlace, Reservation, TimeRange},
         };
 
         fn format_place(place: &ClassPlace, lang: &Language) -> String {
@@ -302,15 +659,19 @@ pub mod common {
                 crate::parsing::types::ClassPlace::Online => {
                     t!("classes.place.online", locale = lang.code()).to_string()
                 }
-                crate::parsing::types::ClassPlace::OnSite { room } => room.trim().to_owned(),
+                crate::parsing::types::ClassPlace::OnSite { room } => room.raw.clone(),
             };
 
             format!("{:<7}", "(".to_owned() + &place + ")")
         }
 
         fn format_timerange(class: &Class) -> (String, String) {
-            let localized_start = class.range.start.with_timezone(&crate::BOT_TIMEZONE);
-            let localized_end = class.range.end.with_timezone(&crate::BOT_TIMEZONE);
+            format_range(&class.range)
+        }
+
+        fn format_range(range: &TimeRange) -> (String, String) {
+            let localized_start = range.start.with_timezone(&crate::BOT_TIMEZONE);
+            let localized_end = range.end.with_timezone(&crate::BOT_TIMEZONE);
 
             let start_time = localized_start.time().format("%H:%M").to_string();
             let end_time = localized_end.time().format("%H:%M").to_string();
@@ -319,11 +680,25 @@ pub mod common {
         }
 
         fn format_kind(class: &Class, lang: &Language) -> String {
+            if let ClassKind::Other(name) = &class.kind {
+                return name.clone();
+            }
+
             let kind = format!("classes.type.{}", class.kind.to_string());
             let kind = t!(kind, locale = lang.code());
             kind.to_string()
         }
 
+        fn format_lecturers(class: &Class) -> String {
+            let names: Vec<_> = class
+                .lecturers
+                .iter()
+                .map(|lecturer| lecturer.full_name())
+                .collect();
+
+            names.join(", ")
+        }
+
         pub fn format_class_long(class: &Class, lang: &Language) -> String {
             let (from, to) = format_timerange(&class);
             t!(
@@ -333,8 +708,124 @@ pub mod common {
                 from = from,
                 to = to,
                 class_type = format_kind(class, lang),
-                lecturer = &class.lecturer,
-                place = format_place(&class.place, &lang)
+                lecturer = format_lecturers(class),
+                place = format_place(&class.place, &lang),
+                mode = class
+                    .study_mode
+                    .map(|mode| t!(format!("classes.mode.{mode}"), locale = lang.code()))
+                    .unwrap_or("-".into()),
+                language = class
+                    .language
+                    .map(|language| t!(
+                        format!("classes.language.{language}"),
+                        locale = lang.code()
+                    ))
+                    .unwrap_or("-".into())
+            )
+            .to_string()
+        }
+
+        /// One line per changed field, describing the new value
+        pub fn format_class_changes(new: &Class, diff: &[ClassField], lang: &Language) -> String {
+            diff.iter()
+                .map(|field| {
+                    let key = format!("notifications.class.changed.{field}");
+                    match field {
+                        ClassField::Name => t!(key, locale = lang.code(), name = &new.name),
+                        ClassField::Kind => {
+                            t!(key, locale = lang.code(), kind = format_kind(new, lang))
+                        }
+                        ClassField::Lecturer => {
+                            t!(key, locale = lang.code(), lecturer = format_lecturers(new))
+                        }
+                        ClassField::Time => {
+                            let (from, to) = format_timerange(new);
+                            t!(key, locale = lang.code(), from = from, to = to)
+                        }
+                        ClassField::Place => {
+                            let place = match &new.place {
+                                ClassPlace::Online => {
+                                    t!("classes.place.online", locale = lang.code()).to_string()
+                                }
+                                ClassPlace::OnSite { room } => room.raw.clone(),
+                            };
+                            t!(key, locale = lang.code(), place = place)
+                        }
+                        ClassField::Groups => {
+                            let groups: Vec<_> =
+                                new.groups.iter().map(|group| group.code.as_str()).collect();
+                            t!(key, locale = lang.code(), groups = groups.join(", "))
+                        }
+                    }
+                })
+                .fold(String::new(), |accum, current| {
+                    format!("{accum}{current}\n")
+                })
+        }
+
+        /// Earliest reminder first, e.g. "10m, 1h30m, 1d"
+        pub fn format_constraints(constraints: &HashSet<NotificationConstraint>) -> String {
+            let mut constraints: Vec<_> = constraints.iter().collect();
+            constraints.sort();
+
+            constraints
+                .iter()
+                .map(|constraint| constraint.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        }
+
+        pub fn format_offset_error(err: &OffsetError, lang: &Language) -> String {
+            match err {
+                OffsetError::Malformed(offset) => t!(
+                    "reminders.error.malformed",
+                    locale = lang.code(),
+                    offset = teloxide::utils::html::escape(offset)
+                ),
+                OffsetError::OutOfRange => t!(
+                    "reminders.error.out_of_range",
+                    locale = lang.code(),
+                    days = NotificationConstraint::MAX_DAYS
+                ),
+                OffsetError::TooMany => t!(
+                    "reminders.error.too_many",
+                    locale = lang.code(),
+                    max = NotificationConstraint::MAX_PER_USER
+                ),
+            }
+            .to_string()
+        }
+
+        pub fn format_reservation_long(reservation: &Reservation, lang: &Language) -> String {
+            let (from, to) = format_range(&reservation.range);
+            let groups: Vec<_> = reservation
+                .groups
+                .iter()
+                .map(|group| group.code.as_str())
+                .collect();
+
+            t!(
+                "reservations.format.long",
+                locale = lang.code(),
+                title = &reservation.title,
+                from = from,
+                to = to,
+                room = reservation.room.trim(),
+                organiser = reservation.organiser.as_deref().unwrap_or("-"),
+                groups = groups.join(", ")
+            )
+            .to_string()
+        }
+
+        pub fn format_reservation_short(reservation: &Reservation, lang: &Language) -> String {
+            let (from, to) = format_range(&reservation.range);
+            t!(
+                "reservations.format.short",
+                locale = lang.code(),
+                title = &reservation.title,
+                from = from,
+                until = to,
+                place = format!("{:<7}", format!("({})", reservation.room.trim()))
             )
             .to_string()
         }
@@ -356,27 +847,44 @@ pub mod common {
 }
 
 pub mod gui {
-    use std::sync::Arc;
+    use std::{
+        collections::{HashMap, HashSet},
+        sync::Arc,
+    };
 
     use bson::doc;
-    use chrono::{DateTime, Days, TimeZone, Timelike, Utc};
-    use futures::StreamExt;
+    use chrono::{DateTime, Datelike, Days, NaiveTime, TimeDelta, TimeZone, Timelike, Utc};
+    use futures::{StreamExt, TryStreamExt};
+    use mongodb::options::{Collation, CollationStrength, ReturnDocument};
+    use smallvec::smallvec;
     use teloxide::{payloads::SendMessageSetters, prelude::Requester, types::ParseMode, Bot};
 
-    use crate::{bot::common::formatters::format_class_short, db::User, parsing::types::Class};
+    use crate::{
+        bot::common::formatters::{
+            format_class_changes, format_class_long, format_class_short, format_constraints,
+            format_offset_error, format_reservation_short,
+        },
+        db::{NotificationConstraint, QuietHours, User, OID},
+        notifications::UpdateEvent,
+        parsing::{
+            manager::day_start,
+            pjatk::deduct,
+            types::{ChangeKind, Class, ClassChange, Reservation},
+        },
+    };
 
     use super::{BotState, HandlerResult, OurBot};
 
+    pub mod parser_control;
     pub mod user_onboard_dialog;
 
     use crate::BOT_TIMEZONE;
 
-    async fn select_classes_for_user_and_date(
+    fn user_day_query(
         date: &DateTime<Utc>,
         user: &User,
-        state: &BotState,
         start_point: Option<DateTime<Utc>>,
-    ) -> eyre::Result<Vec<Class>> {
+    ) -> mongodb::bson::Document {
         // fix for considering days in user's timezone
         let date = date.with_timezone(&BOT_TIMEZONE);
         let start_point = start_point.map(|date| date.with_timezone(&BOT_TIMEZONE));
@@ -391,12 +899,27 @@ pub mod gui {
         final_query.extend(crate::db::create_range_query(&date, start_point).into_iter());
         final_query.extend(doc! {"$or": group_constraints}.into_iter());
 
+        final_query
+    }
+
+    async fn select_classes_for_user_and_date(
+        date: &DateTime<Utc>,
+        user: &User,
+        state: &BotState,
+        start_point: Option<DateTime<Utc>>,
+    ) -> eyre::Result<Vec<Class>> {
+        let final_query = user_day_query(date, user, start_point);
+
         let mut class_query = state.classes_coll.find(final_query).await?;
 
         let mut selected_classes = Vec::default();
 
         while let Some(next_class) = class_query.next().await {
-            selected_classes.push(next_class?);
+            let next_class = next_class?;
+
+            if user.filter.matches(&next_class) {
+                selected_classes.push(next_class);
+            }
         }
 
         selected_classes.sort_by(|first, second| first.range.start.cmp(&second.range.start));
@@ -404,7 +927,32 @@ pub mod gui {
         Ok(selected_classes)
     }
 
-    fn format_shortform_classes(user: &User, classes: &[Class], kind: &str) -> String {
+    // only reservations which mention user's groups, plain room bookings aren't interesting
+    async fn select_reservations_for_user_and_date(
+        date: &DateTime<Utc>,
+        user: &User,
+        state: &BotState,
+    ) -> eyre::Result<Vec<Reservation>> {
+        let final_query = user_day_query(date, user, None);
+
+        let mut reservations: Vec<_> = state
+            .reservations_coll
+            .find(final_query)
+            .await?
+            .try_collect()
+            .await?;
+
+        reservations.sort_by_key(|reservation| reservation.range.start);
+
+        Ok(reservations)
+    }
+
+    fn format_shortform_classes(
+        user: &User,
+        classes: &[Class],
+        reservations: &[Reservation],
+        kind: &str,
+    ) -> String {
         let count_selector = format!("classes.{kind}.ahead.");
 
         let count_line = match classes.is_empty() {
@@ -419,6 +967,11 @@ pub mod gui {
         let class_list = classes
             .iter()
             .map(|class| format_class_short(class, &user.language))
+            .chain(
+                reservations
+                    .iter()
+                    .map(|reservation| format_reservation_short(reservation, &user.language)),
+            )
             .fold(String::new(), |accum, current| {
                 format!("{accum}{current}\n")
             });
@@ -426,19 +979,307 @@ pub mod gui {
         format!("{count_line}\n<pre>{class_list}</pre>")
     }
 
+    /// Today's classes of the user, `None` if there are none
+    pub async fn format_daily_digest(
+        bot_state: &BotState,
+        user: &User,
+    ) -> eyre::Result<Option<String>> {
+        let now = Utc::now();
+
+        let classes = select_classes_for_user_and_date(&now, user, bot_state, None).await?;
+        if classes.is_empty() {
+            return Ok(None);
+        }
+        let reservations = select_reservations_for_user_and_date(&now, user, bot_state).await?;
+
+        Ok(Some(
+            t!(
+                "digest.content",
+                locale = user.language.code(),
+                classes = format_shortform_classes(user, &classes, &reservations, "today")
+            )
+            .to_string(),
+        ))
+    }
+
+    /// Regularly sent lists of classes
+    #[derive(Clone, Copy)]
+    enum Digest {
+        Daily,
+        Weekly,
+    }
+
+    impl Digest {
+        fn time(self, user: &User) -> Option<NaiveTime> {
+            match self {
+                Digest::Daily => user.digest_time,
+                Digest::Weekly => user.weekly_digest_time,
+            }
+        }
+
+        fn field(self) -> &'static str {
+            match self {
+                Digest::Daily => "digest_time",
+                Digest::Weekly => "weekly_digest_time",
+            }
+        }
+
+        fn locale_key(self, key: &str) -> String {
+            match self {
+                Digest::Daily => format!("digest.{key}"),
+                Digest::Weekly => format!("weekly.{key}"),
+            }
+        }
+    }
+
+    async fn format_digest_setting(
+        bot_state: &BotState,
+        user: &User,
+        args: &str,
+        digest: Digest,
+    ) -> eyre::Result<String> {
+        let lang = user.language.code();
+
+        let digest_time = match args.trim() {
+            "" => {
+                return Ok(match digest.time(user) {
+                    Some(time) => t!(
+                        digest.locale_key("current"),
+                        locale = lang,
+                        time = time.format("%H:%M").to_string()
+                    ),
+                    None => t!(digest.locale_key("none"), locale = lang),
+                }
+                .to_string())
+            }
+            "off" => None,
+            time => match NaiveTime::parse_from_str(time, "%H:%M") {
+                Ok(time) => Some(time),
+                Err(_) => return Ok(t!(digest.locale_key("usage"), locale = lang).to_string()),
+            },
+        };
+
+        let Some(updated_user) = bot_state
+            .users_coll
+            .clone_with_type::<OID<User>>()
+            .find_one_and_update(
+                doc! {"id": user.telegram_id.0},
+                doc! {"$set": {digest.field(): bson::to_bson(&digest_time)?}},
+            )
+            .return_document(ReturnDocument::After)
+            .await?
+        else {
+            eyre::bail!("user {} is gone", user.telegram_id);
+        };
+
+        // digest is scheduled along with the rest of user's notifications
+        bot_state
+            .update_tx
+            .send(smallvec![UpdateEvent::UserUpdate { user: updated_user }])
+            .await?;
+
+        Ok(match digest_time {
+            Some(time) => t!(
+                digest.locale_key("saved"),
+                locale = lang,
+                time = time.format("%H:%M").to_string()
+            ),
+            None => t!(digest.locale_key("disabled"), locale = lang),
+        }
+        .to_string())
+    }
+
+    async fn send_digest_setting(
+        bot: OurBot,
+        bot_state: Arc<BotState>,
+        user: User,
+        args: String,
+        digest: Digest,
+    ) -> HandlerResult {
+        let content = format_digest_setting(&bot_state, &user, &args, digest).await?;
+
+        bot.send_message(user.telegram_id, content)
+            .parse_mode(ParseMode::Html)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn digest_setting(
+        bot: OurBot,
+        bot_state: Arc<BotState>,
+        user: User,
+        args: String,
+    ) -> HandlerResult {
+        send_digest_setting(bot, bot_state, user, args, Digest::Daily).await
+    }
+
+    pub async fn weekly_digest_setting(
+        bot: OurBot,
+        bot_state: Arc<BotState>,
+        user: User,
+        args: String,
+    ) -> HandlerResult {
+        send_digest_setting(bot, bot_state, user, args, Digest::Weekly).await
+    }
+
+    async fn format_quiet_hours_setting(
+        bot_state: &BotState,
+        user: &User,
+        args: &str,
+    ) -> eyre::Result<String> {
+        let lang = user.language.code();
+
+        let quiet_hours = match args.trim() {
+            "" => {
+                return Ok(match user.quiet_hours {
+                    Some(quiet_hours) => t!(
+                        "quiet.current",
+                        locale = lang,
+                        hours = quiet_hours.to_string()
+                    ),
+                    None => t!("quiet.none", locale = lang),
+                }
+                .to_string())
+            }
+            "off" => None,
+            hours => match hours.parse::<QuietHours>() {
+                Ok(quiet_hours) => Some(quiet_hours),
+                Err(_) => return Ok(t!("quiet.usage", locale = lang).to_string()),
+            },
+        };
+
+        // applied when messages are sent, so nothing has to be rescheduled
+        bot_state
+            .users_coll
+            .update_one(
+                doc! {"id": user.telegram_id.0},
+                doc! {"$set": {"quiet_hours": bson::to_bson(&quiet_hours)?}},
+            )
+            .await?;
+
+        Ok(match quiet_hours {
+            Some(quiet_hours) => t!(
+                "quiet.saved",
+                locale = lang,
+                hours = quiet_hours.to_string()
+            ),
+            None => t!("quiet.disabled", locale = lang),
+        }
+        .to_string())
+    }
+
+    pub async fn quiet_hours_setting(
+        bot: OurBot,
+        bot_state: Arc<BotState>,
+        user: User,
+        args: String,
+    ) -> HandlerResult {
+        let content = format_quiet_hours_setting(&bot_state, &user, &args).await?;
+
+        bot.send_message(user.telegram_id, content)
+            .parse_mode(ParseMode::Html)
+            .await?;
+        Ok(())
+    }
+
+    /// Classes of the coming Monday to Sunday grouped by day, the ones added or
+    /// changed since the previous preview are marked. `None` if the week is free
+    pub async fn format_weekly_digest(
+        bot_state: &BotState,
+        user: &User,
+    ) -> eyre::Result<Option<String>> {
+        let lang = user.language.code();
+        let now = Utc::now();
+
+        let today = now.with_timezone(&BOT_TIMEZONE).date_naive();
+        let monday = today + Days::new(7 - today.weekday().num_days_from_monday() as u64);
+
+        let since = user.last_weekly_digest.unwrap_or(now - TimeDelta::weeks(1));
+        let mut marks = HashMap::new();
+        // oldest first, so a class added and then changed is still new
+        for change in crate::db::recent_changes(&bot_state.changes_coll, &user.groups, since)
+            .await?
+            .into_iter()
+            .rev()
+        {
+            let mark = match change.kind {
+                ChangeKind::Added => "weekly.mark.added",
+                ChangeKind::Modified => "weekly.mark.modified",
+                ChangeKind::Removed => continue,
+            };
+            marks.entry(change.class_id).or_insert(mark);
+        }
+
+        let mut days = Vec::new();
+        let mut highlighted = false;
+        for day in (0..7).map(|offset| monday + Days::new(offset)) {
+            let date = day_start(day)?.with_timezone(&Utc);
+            let classes = select_classes_for_user_and_date(&date, user, bot_state, None).await?;
+            if classes.is_empty() {
+                continue;
+            }
+
+            let lines = classes
+                .iter()
+                .map(|class| {
+                    let mark = marks.get(&class.class_id).map_or_else(String::new, |mark| {
+                        highlighted = true;
+                        t!(*mark, locale = lang).to_string()
+                    });
+                    format!("{mark}{}\n", format_class_short(class, &user.language))
+                })
+                .collect::<String>();
+
+            let title = t!(
+                format!(
+                    "weekly.weekday.{}",
+                    day.weekday().to_string().to_lowercase()
+                ),
+                locale = lang
+            );
+            days.push(format!(
+                "<b>{title} {}</b>\n<pre>{lines}</pre>",
+                day.format("%d.%m")
+            ));
+        }
+
+        if days.is_empty() {
+            return Ok(None);
+        }
+
+        let legend = match highlighted {
+            true => t!("weekly.legend", locale = lang).to_string(),
+            false => String::new(),
+        };
+
+        Ok(Some(
+            t!(
+                "weekly.content",
+                locale = lang,
+                days = days.join("\n"),
+                legend = legend
+            )
+            .to_string(),
+        ))
+    }
+
     async fn format_mainmenu(bot_state: &BotState, user: &User) -> eyre::Result<String> {
+        let tomorrow = Utc::now().checked_add_days(Days::new(1)).unwrap();
+
         let today_classes =
             select_classes_for_user_and_date(&Utc::now(), &user, &bot_state, None).await?;
-        let tomorrow_classes = select_classes_for_user_and_date(
-            &(Utc::now().checked_add_days(Days::new(1)).unwrap()),
-            &user,
-            &bot_state,
-            None,
-        )
-        .await?;
+        let tomorrow_classes =
+            select_classes_for_user_and_date(&tomorrow, &user, &bot_state, None).await?;
+
+        let today_reservations =
+            select_reservations_for_user_and_date(&Utc::now(), user, bot_state).await?;
+        let tomorrow_reservations =
+            select_reservations_for_user_and_date(&tomorrow, user, bot_state).await?;
 
-        let today_classes = format_shortform_classes(&user, &today_classes, "today");
-        let tomorrow_classes = format_shortform_classes(&user, &tomorrow_classes, "tomorrow");
+        let today_classes =
+            format_shortform_classes(&user, &today_classes, &today_reservations, "today");
+        let tomorrow_classes =
+            format_shortform_classes(&user, &tomorrow_classes, &tomorrow_reservations, "tomorrow");
 
         let current_time = Utc::now().with_timezone(&crate::BOT_TIMEZONE).time();
 
@@ -462,6 +1303,257 @@ pub mod gui {
         .to_string())
     }
 
+    async fn format_lecturer_location(
+        bot_state: &BotState,
+        user: &User,
+        name: &str,
+    ) -> eyre::Result<String> {
+        let lang = user.language.code();
+
+        let Some(lecturer) = deduct::parse_lecturers(name).into_iter().next() else {
+            return Ok(t!("lecturer.usage", locale = lang).to_string());
+        };
+
+        let mut lecturer_query = doc! {"last_name": &lecturer.last_name};
+        if !lecturer.first_name.is_empty() {
+            lecturer_query.insert("first_name", &lecturer.first_name);
+        }
+
+        // typed by the user, so can't go into the message as is
+        let lecturer_name = teloxide::utils::html::escape(&lecturer.full_name());
+
+        let now = Utc::now();
+        let day_end = now
+            .with_timezone(&BOT_TIMEZONE)
+            .with_time(NaiveTime::from_hms_opt(23, 59, 59).unwrap())
+            .unwrap();
+
+        let query = doc! {
+            "lecturers": {"$elemMatch": lecturer_query},
+            "range.end": {"$gt": bson::DateTime::from(now)},
+            "range.start": {"$lt": bson::DateTime::from(day_end)},
+        };
+
+        // names are typed by users, so case shouldn't matter
+        let collation = Collation::builder()
+            .locale("pl")
+            .strength(CollationStrength::Secondary)
+            .build();
+
+        let class = bot_state
+            .classes_coll
+            .find_one(query)
+            .sort(doc! {"range.start": 1})
+            .collation(collation)
+            .await?;
+
+        let Some(class) = class else {
+            return Ok(t!("lecturer.none", locale = lang, lecturer = &lecturer_name).to_string());
+        };
+
+        let key = match class.range.start <= now {
+            true => "lecturer.now",
+            false => "lecturer.next",
+        };
+
+        Ok(t!(
+            key,
+            locale = lang,
+            lecturer = &lecturer_name,
+            content = format_class_long(&class, &user.language)
+        )
+        .to_string())
+    }
+
+    const CHANGES_DEFAULT_DAYS: i64 = 7;
+    const CHANGES_MAX_DAYS: i64 = 31;
+    // keeps the reply within one telegram message
+    const CHANGES_MAX_SHOWN: usize = 30;
+
+    fn format_change(change: &ClassChange, lang: &crate::db::Language) -> Option<String> {
+        let class = change.class()?;
+        let date = class
+            .range
+            .start
+            .with_timezone(&BOT_TIMEZONE)
+            .format("%d.%m")
+            .to_string();
+
+        let line = t!(
+            format!("changes.{}", change.kind),
+            locale = lang.code(),
+            date = date,
+            content = format_class_short(class, lang)
+        )
+        .to_string();
+
+        Some(match change.kind {
+            ChangeKind::Modified => format!(
+                "{line}\n{}",
+                format_class_changes(class, &change.diff, lang)
+            ),
+            _ => line,
+        })
+    }
+
+    async fn format_recent_changes(
+        bot_state: &BotState,
+        user: &User,
+        days: &str,
+    ) -> eyre::Result<String> {
+        let lang = user.language.code();
+
+        let days = match days.trim() {
+            "" => CHANGES_DEFAULT_DAYS,
+            days => match days.parse() {
+                Ok(days) if (1..=CHANGES_MAX_DAYS).contains(&days) => days,
+                _ => {
+                    return Ok(
+                        t!("changes.usage", locale = lang, max = CHANGES_MAX_DAYS).to_string()
+                    )
+                }
+            },
+        };
+
+        let since = Utc::now() - TimeDelta::days(days);
+        let changes: Vec<_> =
+            crate::db::recent_changes(&bot_state.changes_coll, &user.groups, since)
+                .await?
+                .into_iter()
+                .filter(|change| {
+                    change
+                        .class()
+                        .is_some_and(|class| user.filter.matches(class))
+                })
+                .collect();
+
+        if changes.is_empty() {
+            return Ok(t!("changes.none", locale = lang, days = days).to_string());
+        }
+
+        let mut lines: Vec<_> = changes
+            .iter()
+            .take(CHANGES_MAX_SHOWN)
+            .filter_map(|change| format_change(change, &user.language))
+            .collect();
+        if changes.len() > CHANGES_MAX_SHOWN {
+            lines.push(
+                t!(
+                    "changes.more",
+                    locale = lang,
+                    count = changes.len() - CHANGES_MAX_SHOWN
+                )
+                .to_string(),
+            );
+        }
+
+        Ok(t!(
+            "changes.title",
+            locale = lang,
+            days = days,
+            content = lines.join("\n")
+        )
+        .to_string())
+    }
+
+    pub async fn recent_changes(
+        bot: OurBot,
+        bot_state: Arc<BotState>,
+        user: User,
+        days: String,
+    ) -> HandlerResult {
+        let content = format_recent_changes(&bot_state, &user, &days).await?;
+
+        bot.send_message(user.telegram_id, content)
+            .parse_mode(ParseMode::Html)
+            .await?;
+        Ok(())
+    }
+
+    async fn format_reminders(
+        bot_state: &BotState,
+        user: &User,
+        args: &str,
+    ) -> eyre::Result<String> {
+        let lang = user.language.code();
+
+        let constraints = match args.trim() {
+            "" => {
+                return Ok(match user.constraints.is_empty() {
+                    true => t!("reminders.none", locale = lang),
+                    false => t!(
+                        "reminders.current",
+                        locale = lang,
+                        reminders = format_constraints(&user.constraints)
+                    ),
+                }
+                .to_string())
+            }
+            "off" => HashSet::new(),
+            offsets => match NotificationConstraint::parse_list(offsets) {
+                Ok(constraints) => constraints,
+                Err(err) => return Ok(format_offset_error(&err, &user.language)),
+            },
+        };
+
+        let Some(updated_user) = bot_state
+            .users_coll
+            .clone_with_type::<OID<User>>()
+            .find_one_and_update(
+                doc! {"id": user.telegram_id.0},
+                doc! {"$set": {"constraints": bson::to_bson(&constraints)?}},
+            )
+            .return_document(ReturnDocument::After)
+            .await?
+        else {
+            eyre::bail!("user {} is gone", user.telegram_id);
+        };
+
+        // notifications are rebuilt for the new set of reminders
+        bot_state
+            .update_tx
+            .send(smallvec![UpdateEvent::UserUpdate { user: updated_user }])
+            .await?;
+
+        Ok(match constraints.is_empty() {
+            true => t!("reminders.disabled", locale = lang),
+            false => t!(
+                "reminders.saved",
+                locale = lang,
+                reminders = format_constraints(&constraints)
+            ),
+        }
+        .to_string())
+    }
+
+    pub async fn reminders(
+        bot: OurBot,
+        bot_state: Arc<BotState>,
+        user: User,
+        args: String,
+    ) -> HandlerResult {
+        let content = format_reminders(&bot_state, &user, &args).await?;
+
+        bot.send_message(user.telegram_id, content)
+            .parse_mode(ParseMode::Html)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn lecturer_location(
+        bot: OurBot,
+        bot_state: Arc<BotState>,
+        user: User,
+        name: String,
+    ) -> HandlerResult {
+        let content = format_lecturer_location(&bot_state, &user, &name).await?;
+
+        bot.send_message(user.telegram_id, content)
+            .parse_mode(ParseMode::Html)
+            .await?;
+        Ok(())
+    }
+
     pub async fn main_menu(bot: OurBot, bot_state: Arc<BotState>, user: User) -> HandlerResult {
         bot.send_message(user.telegram_id, format_mainmenu(&bot_state, &user).await?)
             .parse_mode(ParseMode::Html)
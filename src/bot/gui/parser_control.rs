@@ -0,0 +1,293 @@
+use std::sync::Arc;
+
+use chrono::NaiveDate;
+use teloxide::{payloads::SendMessageSetters, prelude::Requester, types::ParseMode};
+
+use crate::{
+    bot::{BotState, HandlerResult, OurBot},
+    db::{Role, User},
+    parsing::control::{self, Control, ControlReply},
+};
+
+fn parse_date(raw: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(raw, "%Y-%m-%d").ok()
+}
+
+// admins aren't kept waiting on a parser busy with a long parse
+const REPLY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+// `status|pause|resume [parser]`, `reparse [parser] <date> [end date] [force]`
+// or `confirm <parser> <date>`, parser is omitted to address all of them.
+// Removals held back are applied only by `force` or `confirm`, which
+// need a parser, so other parsers don't have their days wiped too
+fn parse_args(args: &str) -> Option<(Option<&str>, Control)> {
+    let mut args = args.split_whitespace();
+    let verb = args.next()?;
+    let rest: Vec<_> = args.collect();
+
+    let simple = |control| match rest.as_slice() {
+        [] => Some((None, control)),
+        [parser] => Some((Some(*parser), control)),
+        _ => None,
+    };
+
+    match verb {
+        "status" => simple(Control::Status),
+        "pause" => simple(Control::Pause),
+        "resume" => simple(Control::Resume),
+        "reparse" | "confirm" => {
+            let (force, rest) = match rest.split_last() {
+                Some((&"force", rest)) if verb == "reparse" => (true, rest),
+                _ => (verb == "confirm", rest.as_slice()),
+            };
+
+            let (parser, dates) = match rest.split_first() {
+                Some((first, dates)) if parse_date(first).is_none() => (Some(*first), dates),
+                _ => (None, rest),
+            };
+
+            let (start, end) = match dates {
+                [day] => (parse_date(day)?, parse_date(day)?),
+                [start, end] if verb == "reparse" => (parse_date(start)?, parse_date(end)?),
+                _ => return None,
+            };
+            if force && parser.is_none() {
+                return None;
+            }
+
+            Some((
+                parser,
+                Control::Reparse {
+                    start,
+                    end,
+                    allow_mass_removal: force,
+                },
+            ))
+        }
+        _ => None,
+    }
+}
+
+fn format_reply(reply: ControlReply, lang: &str) -> String {
+    let optional_day =
+        |day: Option<NaiveDate>| day.map_or_else(|| "-".to_owned(), |day| day.to_string());
+    let days = |days: &[NaiveDate]| match days {
+        [] => "-".to_owned(),
+        days => days
+            .iter()
+            .map(NaiveDate::to_string)
+            .collect::<Vec<_>>()
+            .join(", "),
+    };
+
+    match reply {
+        ControlReply::Reparsed {
+            days: days_parsed,
+            added,
+            removed,
+            modified,
+            held,
+        } => {
+            let mut reply = t!(
+                "admin.parser.reparsed",
+                locale = lang,
+                days = days_parsed,
+                added = added,
+                removed = removed,
+                modified = modified
+            )
+            .to_string();
+
+            if !held.is_empty() {
+                reply.push_str(&t!("admin.parser.held", locale = lang, days = days(&held)));
+            }
+
+            reply
+        }
+        ControlReply::Paused => t!("admin.parser.paused", locale = lang).to_string(),
+        ControlReply::Resumed => t!("admin.parser.resumed", locale = lang).to_string(),
+        ControlReply::Status(status) => {
+            let state = match status.paused {
+                true => t!("admin.parser.state.paused", locale = lang),
+                false => t!("admin.parser.state.running", locale = lang),
+            };
+
+            t!(
+                "admin.parser.status",
+                locale = lang,
+                state = state,
+                last_day = optional_day(status.last_day_parsed),
+                failures = status.consecutive_failures,
+                held = days(&status.held_removals)
+            )
+            .to_string()
+        }
+        ControlReply::Failed(err) => t!(
+            "admin.parser.failed",
+            locale = lang,
+            error = teloxide::utils::html::escape(&err)
+        )
+        .to_string(),
+    }
+}
+
+async fn format_parser_control(bot_state: &BotState, user: &User, args: &str) -> String {
+    let lang = user.language.code();
+
+    let Some((parser, control)) = parse_args(args) else {
+        return t!("admin.parser.usage", locale = lang).to_string();
+    };
+
+    let parsers: Vec<_> = match parser {
+        Some(parser) => match bot_state.parser_controls.get_key_value(parser) {
+            Some(parser) => vec![parser],
+            None => {
+                let known: Vec<_> = bot_state.parser_controls.keys().copied().collect();
+                return t!(
+                    "admin.parser.unknown",
+                    locale = lang,
+                    parser = teloxide::utils::html::escape(parser),
+                    known = known.join(", ")
+                )
+                .to_string();
+            }
+        },
+        None => bot_state.parser_controls.iter().collect(),
+    };
+
+    let mut replies = Vec::with_capacity(parsers.len());
+    for (name, parser) in parsers {
+        let reply = match tokio::time::timeout(
+            REPLY_TIMEOUT,
+            control::send(parser, control.clone()),
+        )
+        .await
+        {
+            Ok(Ok(reply)) => format_reply(reply, lang),
+            Ok(Err(err)) => {
+                slog::error!(bot_state.logger, "parser_control.send_failed"; "parser" => name, "err" => ?err);
+                t!("admin.parser.unavailable", locale = lang).to_string()
+            }
+            Err(_) => {
+                slog::warn!(bot_state.logger, "parser_control.reply_timeout"; "parser" => name);
+                t!("admin.parser.busy", locale = lang).to_string()
+            }
+        };
+
+        replies.push(
+            t!(
+                "admin.parser.reply",
+                locale = lang,
+                parser = name,
+                reply = reply
+            )
+            .to_string(),
+        );
+    }
+
+    replies.join("\n")
+}
+
+pub async fn parser_control(
+    bot: OurBot,
+    bot_state: Arc<BotState>,
+    user: User,
+    args: String,
+) -> HandlerResult {
+    // others don't even learn the command exists
+    if !matches!(user.role, Role::Admin) {
+        return Ok(());
+    }
+
+    let content = format_parser_control(&bot_state, &user, &args).await;
+
+    bot.send_message(user.telegram_id, content)
+        .parse_mode(ParseMode::Html)
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 10, day).unwrap()
+    }
+
+    fn reparse(start: u32, end: u32, allow_mass_removal: bool) -> Control {
+        Control::Reparse {
+            start: day(start),
+            end: day(end),
+            allow_mass_removal,
+        }
+    }
+
+    #[test]
+    fn simple_commands_take_an_optional_parser() {
+        assert_eq!(parse_args("status"), Some((None, Control::Status)));
+        assert_eq!(
+            parse_args("pause pjatk"),
+            Some((Some("pjatk"), Control::Pause))
+        );
+        assert_eq!(
+            parse_args(" resume   ics "),
+            Some((Some("ics"), Control::Resume))
+        );
+
+        assert_eq!(parse_args("status pjatk ics"), None);
+        assert_eq!(parse_args(""), None);
+        assert_eq!(parse_args("restart"), None);
+    }
+
+    #[test]
+    fn reparse_takes_a_day_or_a_range() {
+        assert_eq!(
+            parse_args("reparse 2024-10-14"),
+            Some((None, reparse(14, 14, false)))
+        );
+        assert_eq!(
+            parse_args("reparse pjatk 2024-10-14 2024-10-20"),
+            Some((Some("pjatk"), reparse(14, 20, false)))
+        );
+
+        // parser is told apart from a date only by not being one
+        assert_eq!(parse_args("reparse 14.10.2024"), None);
+        assert_eq!(parse_args("reparse pjatk 14.10.2024"), None);
+        assert_eq!(parse_args("reparse pjatk"), None);
+        assert_eq!(
+            parse_args("reparse pjatk 2024-10-14 2024-10-15 2024-10-16"),
+            None
+        );
+    }
+
+    #[test]
+    fn force_needs_reparse_of_a_parser() {
+        assert_eq!(
+            parse_args("reparse pjatk 2024-10-14 force"),
+            Some((Some("pjatk"), reparse(14, 14, true)))
+        );
+        assert_eq!(
+            parse_args("reparse pjatk 2024-10-14 2024-10-20 force"),
+            Some((Some("pjatk"), reparse(14, 20, true)))
+        );
+
+        // would wipe days of every parser
+        assert_eq!(parse_args("reparse 2024-10-14 force"), None);
+        // only last, and only for reparse
+        assert_eq!(parse_args("reparse pjatk force 2024-10-14"), None);
+        assert_eq!(parse_args("confirm pjatk 2024-10-14 force"), None);
+    }
+
+    #[test]
+    fn confirm_takes_a_parser_and_a_single_day() {
+        assert_eq!(
+            parse_args("confirm pjatk 2024-10-14"),
+            Some((Some("pjatk"), reparse(14, 14, true)))
+        );
+
+        assert_eq!(parse_args("confirm 2024-10-14"), None);
+        assert_eq!(parse_args("confirm pjatk 2024-10-14 2024-10-20"), None);
+        assert_eq!(parse_args("confirm pjatk"), None);
+    }
+}
@@ -1,4 +1,4 @@
-use std::sync::Arc;
+use std::{collections::HashSet, sync::Arc};
 
 use rust_i18n::t;
 use strum::IntoEnumIterator;
@@ -13,14 +13,13 @@ use teloxide::{
 
 use crate::{
     bot::{create_storage, BotDialogue, BotState, DialogueStorage, OurBot},
-    db::{Language, NotificationConstraint},
-    parsing::types::Group,
+    db::{ClassFilter, Language, NotificationConstraint},
+    parsing::types::{self, Group, StudyMode},
 };
 
+/// Usual reminders, toggled with buttons. Any other offset can be typed in
 #[derive(strum::EnumIter, strum::Display, strum::EnumString, Clone)]
 pub enum Notification {
-    #[strum(serialize = "nothing")]
-    No,
     #[strum(serialize = "_10mins")]
     _10Mins,
     #[strum(serialize = "_30mins")]
@@ -29,22 +28,63 @@ pub enum Notification {
     _1Hour,
     #[strum(serialize = "_2hours")]
     _2Hours,
+    /// finishes onboarding with whatever was chosen, maybe nothing
+    #[strum(serialize = "done")]
+    Done,
 }
 
 impl Notification {
     fn constraint(self) -> Option<NotificationConstraint> {
         let duration = match self {
-            Notification::No => None,
             Notification::_10Mins => Some(std::time::Duration::from_secs(10 * 60)),
             Notification::_30Mins => Some(std::time::Duration::from_secs(30 * 60)),
             Notification::_1Hour => Some(std::time::Duration::from_secs(60 * 60)),
             Notification::_2Hours => Some(std::time::Duration::from_secs(120 * 60)),
+            Notification::Done => None,
         };
 
         duration.map(NotificationConstraint)
     }
 }
 
+#[derive(strum::EnumIter, strum::Display, strum::EnumString, Clone)]
+pub enum ClassFilterChoice {
+    #[strum(serialize = "all")]
+    All,
+    #[strum(serialize = "english")]
+    English,
+    #[strum(serialize = "polish")]
+    Polish,
+    #[strum(serialize = "onsite")]
+    OnSite,
+    #[strum(serialize = "online")]
+    Online,
+}
+
+impl ClassFilterChoice {
+    fn filter(self) -> ClassFilter {
+        match self {
+            ClassFilterChoice::All => ClassFilter::default(),
+            ClassFilterChoice::English => ClassFilter {
+                language: Some(types::Language::English),
+                ..Default::default()
+            },
+            ClassFilterChoice::Polish => ClassFilter {
+                language: Some(types::Language::Polish),
+                ..Default::default()
+            },
+            ClassFilterChoice::OnSite => ClassFilter {
+                study_mode: Some(StudyMode::OnSite),
+                ..Default::default()
+            },
+            ClassFilterChoice::Online => ClassFilter {
+                study_mode: Some(StudyMode::Online),
+                ..Default::default()
+            },
+        }
+    }
+}
+
 #[derive(Default, Clone)]
 pub enum Stages {
     #[default]
@@ -53,9 +93,15 @@ pub enum Stages {
     WaitingForGroups {
         language: Language,
     },
+    WaitingForClassFilter {
+        groups: Vec<Group>,
+        language: Language,
+    },
     WaitingForNotifications {
         groups: Vec<Group>,
         language: Language,
+        filter: ClassFilter,
+        constraints: HashSet<NotificationConstraint>,
     },
 }
 
@@ -70,20 +116,44 @@ pub fn deps() -> DependencyMap {
             .branch(
                 Update::filter_callback_query()
                     .branch(dptree::case![Stages::WaitingForLanguage].endpoint(handlers::handle_language_selection))
-                    .branch(dptree::case![Stages::WaitingForNotifications { groups, language }].endpoint(handlers::handle_notifications_choice))
+                    .branch(dptree::case![Stages::WaitingForClassFilter { groups, language }].endpoint(handlers::handle_class_filter_choice))
+                    .branch(dptree::case![Stages::WaitingForNotifications { groups, language, filter, constraints }].endpoint(handlers::handle_notifications_choice))
             )
 
             .branch(
                 Update::filter_message()
                     .branch(dptree::case![Stages::Start].endpoint(entrypoint))
                     .branch(dptree::case![Stages::WaitingForGroups {language}].endpoint(handlers::handle_group_selection))    
+                    .branch(dptree::case![Stages::WaitingForNotifications { groups, language, filter, constraints }].endpoint(handlers::handle_notifications_typed))
             )
     }
 
-fn format_notifications_keyboard() -> InlineKeyboardMarkup {
+// chosen presets are marked, done button tells whether anything was chosen
+fn format_notifications_keyboard(
+    language: &Language,
+    constraints: &HashSet<NotificationConstraint>,
+) -> InlineKeyboardMarkup {
     let buttons = Notification::iter().map(|notification_type| {
+        let text = match (&notification_type, notification_type.clone().constraint()) {
+            (Notification::Done, _) if constraints.is_empty() => {
+                t!("onboarding.notifications.nothing", locale = language.code()).to_string()
+            }
+            (_, Some(constraint)) if constraints.contains(&constraint) => format!(
+                "✅ {}",
+                t!(
+                    format!("onboarding.notifications.{notification_type}"),
+                    locale = language.code()
+                )
+            ),
+            _ => t!(
+                format!("onboarding.notifications.{notification_type}"),
+                locale = language.code()
+            )
+            .to_string(),
+        };
+
         vec![InlineKeyboardButton {
-            text: t!(format!("onboarding.notifications.{}", notification_type)).to_string(),
+            text,
             kind: teloxide::types::InlineKeyboardButtonKind::CallbackData(
                 notification_type.to_string(),
             ),
@@ -95,6 +165,23 @@ fn format_notifications_keyboard() -> InlineKeyboardMarkup {
     }
 }
 
+fn format_class_filter_keyboard(language: &Language) -> InlineKeyboardMarkup {
+    let buttons = ClassFilterChoice::iter().map(|choice| {
+        vec![InlineKeyboardButton {
+            text: t!(
+                format!("onboarding.class_filter.{}", choice),
+                locale = language.code()
+            )
+            .to_string(),
+            kind: teloxide::types::InlineKeyboardButtonKind::CallbackData(choice.to_string()),
+        }]
+    });
+
+    InlineKeyboardMarkup {
+        inline_keyboard: buttons.collect(),
+    }
+}
+
 fn format_languages_keyboard() -> InlineKeyboardMarkup {
     let buttons = Language::iter().map(|lang| {
         vec![InlineKeyboardButton {
@@ -134,12 +221,14 @@ mod senders {
         Bot,
     };
 
+    use std::collections::HashSet;
+
     use crate::{
-        bot::{HandlerResult, OurBot},
-        db::Language,
+        bot::{common::formatters::format_constraints, HandlerResult, OurBot},
+        db::{Language, NotificationConstraint},
     };
 
-    use super::format_notifications_keyboard;
+    use super::{format_class_filter_keyboard, format_notifications_keyboard};
 
     pub async fn send_groups_selection(
         bot: OurBot,
@@ -162,22 +251,75 @@ mod senders {
         Ok(())
     }
 
-    pub async fn send_notifications_prompt(
+    pub async fn send_class_filter_prompt(
         bot: OurBot,
         user_id: ChatId,
         language: &Language,
     ) -> HandlerResult {
-        let languages_keyboard = format_notifications_keyboard();
+        let prompt = t!("onboarding.class_filter.prompt", locale = language.code());
+
+        bot.send_message(user_id, prompt)
+            .parse_mode(ParseMode::Html)
+            .reply_markup(format_class_filter_keyboard(language))
+            .await?;
 
+        Ok(())
+    }
+
+    fn format_notifications_prompt(
+        language: &Language,
+        constraints: &HashSet<NotificationConstraint>,
+    ) -> String {
         let prompt = t!("onboarding.notifications.prompt", locale = language.code());
+        if constraints.is_empty() {
+            return prompt.to_string();
+        }
 
-        bot.send_message(user_id, prompt)
+        let chosen = t!(
+            "onboarding.notifications.chosen",
+            locale = language.code(),
+            reminders = format_constraints(constraints)
+        );
+        format!("{prompt}\n{chosen}")
+    }
+
+    pub async fn send_notifications_prompt(
+        bot: OurBot,
+        user_id: ChatId,
+        language: &Language,
+        constraints: &HashSet<NotificationConstraint>,
+    ) -> HandlerResult {
+        bot.send_message(user_id, format_notifications_prompt(language, constraints))
             .parse_mode(ParseMode::Html)
-            .reply_markup(languages_keyboard)
+            .reply_markup(format_notifications_keyboard(language, constraints))
             .await?;
 
         Ok(())
     }
+
+    // the prompt is kept in place while presets are toggled
+    pub async fn update_notifications_prompt(
+        bot: OurBot,
+        user_id: ChatId,
+        msg: Option<MaybeInaccessibleMessage>,
+        language: &Language,
+        constraints: &HashSet<NotificationConstraint>,
+    ) -> HandlerResult {
+        let Some(MaybeInaccessibleMessage::Regular(msg)) = msg else {
+            return send_notifications_prompt(bot, user_id, language, constraints).await;
+        };
+
+        bot.edit_message_text(
+            user_id,
+            msg.id,
+            format_notifications_prompt(language, constraints),
+        )
+        .parse_mode(ParseMode::Html)
+        .reply_markup(format_notifications_keyboard(language, constraints))
+        .await?;
+
+        Ok(())
+    }
 }
 
 mod handlers {
@@ -194,13 +336,16 @@ mod handlers {
     };
 
     use crate::{
-        bot::{self, BotDialogue, BotState, HandlerResult, OurBot},
-        db::{self, Language, OID},
+        bot::{
+            self, common::formatters::format_offset_error, BotDialogue, BotState, HandlerResult,
+            OurBot,
+        },
+        db::{self, ClassFilter, Language, NotificationConstraint, OffsetError, OID},
         notifications::UpdateEvent,
         parsing::types::Group,
     };
 
-    use super::{senders, Notification, Stages};
+    use super::{senders, ClassFilterChoice, Notification, Stages};
 
     type Type = HandlerResult;
 
@@ -274,10 +419,10 @@ mod handlers {
             }
         }
 
-        senders::send_notifications_prompt(bot, message.chat.id, &language).await?;
+        senders::send_class_filter_prompt(bot, message.chat.id, &language).await?;
 
         dialogue
-            .update(Stages::WaitingForNotifications {
+            .update(Stages::WaitingForClassFilter {
                 groups: group_chunks,
                 language,
             })
@@ -286,12 +431,50 @@ mod handlers {
         Ok(())
     }
 
-    pub async fn handle_notifications_choice(
+    pub async fn handle_class_filter_choice(
         bot: OurBot,
         (groups, language): (Vec<Group>, Language),
         state: Arc<BotState>,
         answer: CallbackQuery,
         dialogue: BotDialogue<Stages>,
+    ) -> HandlerResult {
+        let Some(answer_data) = answer.data else {
+            slog::error!(state.logger, "onboard.handle_class_filter_choice"; "err" => "haven't received callback data");
+            return Ok(());
+        };
+
+        let Ok(filter_choice) = ClassFilterChoice::from_str(&answer_data) else {
+            slog::error!(state.logger, "onboard.handle_class_filter_choice"; "err" => "couldn't parse choice");
+            return Ok(());
+        };
+
+        let constraints = HashSet::new();
+        senders::send_notifications_prompt(bot, answer.from.id.into(), &language, &constraints)
+            .await?;
+
+        dialogue
+            .update(Stages::WaitingForNotifications {
+                groups,
+                language,
+                filter: filter_choice.filter(),
+                constraints,
+            })
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn handle_notifications_choice(
+        bot: OurBot,
+        (groups, language, filter, mut constraints): (
+            Vec<Group>,
+            Language,
+            ClassFilter,
+            HashSet<NotificationConstraint>,
+        ),
+        state: Arc<BotState>,
+        answer: CallbackQuery,
+        dialogue: BotDialogue<Stages>,
     ) -> HandlerResult {
         let Some(answer_data) = answer.data else {
             slog::error!(state.logger, "onboard.handle_notification_choice"; "err" => "haven't received callback data");
@@ -303,14 +486,35 @@ mod handlers {
             return Ok(());
         };
 
-        let constraints = match notification_choice.constraint() {
-            Some(constraint) => {
-                let mut set = HashSet::default();
-                set.insert(constraint);
-                set
+        // presets are toggled until user is done
+        if let Some(constraint) = notification_choice.constraint() {
+            if !constraints.remove(&constraint) {
+                if constraints.len() >= NotificationConstraint::MAX_PER_USER {
+                    return Ok(());
+                }
+                constraints.insert(constraint);
             }
-            None => HashSet::default(),
-        };
+
+            senders::update_notifications_prompt(
+                bot,
+                answer.from.id.into(),
+                answer.message,
+                &language,
+                &constraints,
+            )
+            .await?;
+
+            dialogue
+                .update(Stages::WaitingForNotifications {
+                    groups,
+                    language,
+                    filter,
+                    constraints,
+                })
+                .await?;
+
+            return Ok(());
+        }
 
         let new_user = OID {
             data: db::User {
@@ -319,6 +523,11 @@ mod handlers {
                 groups,
                 language,
                 constraints,
+                filter,
+                digest_time: None,
+                weekly_digest_time: None,
+                last_weekly_digest: None,
+                quiet_hours: None,
                 join_date: Utc::now(),
             },
             id: ObjectId::new(),
@@ -342,4 +551,46 @@ mod handlers {
 
         Ok(())
     }
+
+    // offsets which aren't among presets are typed in, e.g. "15m 1d"
+    pub async fn handle_notifications_typed(
+        bot: OurBot,
+        (groups, language, filter, mut constraints): (
+            Vec<Group>,
+            Language,
+            ClassFilter,
+            HashSet<NotificationConstraint>,
+        ),
+        message: Message,
+        dialogue: BotDialogue<Stages>,
+    ) -> HandlerResult {
+        let typed = NotificationConstraint::parse_list(message.text().unwrap_or_default())
+            .and_then(|typed| {
+                constraints.extend(typed);
+                match constraints.len() > NotificationConstraint::MAX_PER_USER {
+                    true => Err(OffsetError::TooMany),
+                    false => Ok(()),
+                }
+            });
+
+        if let Err(err) = typed {
+            bot.send_message(message.chat.id, format_offset_error(&err, &language))
+                .parse_mode(ParseMode::Html)
+                .await?;
+            return Ok(());
+        }
+
+        senders::send_notifications_prompt(bot, message.chat.id, &language, &constraints).await?;
+
+        dialogue
+            .update(Stages::WaitingForNotifications {
+                groups,
+                language,
+                filter,
+                constraints,
+            })
+            .await?;
+
+        Ok(())
+    }
 }
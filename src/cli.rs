@@ -0,0 +1,258 @@
+use std::path::PathBuf;
+
+use chrono::NaiveDate;
+
+const USAGE: &str = "\
+usage:
+    schedulebot [config.toml]
+        run the bot
+    schedulebot diff export <snapshot> [--day YYYY-MM-DD] [--config config.toml]
+        dump classes to a snapshot, as a JSON array if it ends with .json, NDJSON otherwise
+    schedulebot diff <old snapshot> <new snapshot>
+        print classes added, removed or modified between two snapshots
+    schedulebot parse --from YYYY-MM-DD --to YYYY-MM-DD [--parser pjatk] [--dry-run] [--silent] [--allow-mass-removal] [--config config.toml]
+        parse the days into the database right away and print what has changed to stderr,
+        --dry-run writes nothing, --silent doesn't tell users about the changes
+        (their reminders are then scheduled on the next start of the bot),
+        --allow-mass-removal applies days which would lose too many classes at once";
+
+#[derive(Debug)]
+pub enum Command {
+    Run,
+    DiffExport {
+        output: PathBuf,
+        day: Option<NaiveDate>,
+    },
+    DiffCompare {
+        old: PathBuf,
+        new: PathBuf,
+    },
+    Parse(ParseArgs),
+}
+
+/// Days and flags of `schedulebot parse`
+#[derive(Debug)]
+pub struct ParseArgs {
+    pub from: NaiveDate,
+    pub to: NaiveDate,
+    /// name of a configured parser
+    pub parser: String,
+    pub dry_run: bool,
+    pub silent: bool,
+    pub allow_mass_removal: bool,
+}
+
+#[derive(Debug)]
+pub struct Args {
+    pub config: PathBuf,
+    pub command: Command,
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("{reason}\n\n{USAGE}")]
+pub struct UsageError {
+    reason: String,
+}
+
+fn usage(reason: impl Into<String>) -> UsageError {
+    UsageError {
+        reason: reason.into(),
+    }
+}
+
+fn parse_day(raw: &str) -> Result<NaiveDate, UsageError> {
+    NaiveDate::parse_from_str(raw, "%Y-%m-%d").map_err(|_| usage(format!("'{raw}' isn't a date")))
+}
+
+impl Args {
+    pub fn from_env() -> Result<Self, UsageError> {
+        Self::parse(std::env::args().skip(1).collect())
+    }
+
+    fn parse(args: Vec<String>) -> Result<Self, UsageError> {
+        let mut config = PathBuf::from("config.toml");
+        let mut day = None;
+        let mut from = None;
+        let mut to = None;
+        let mut parser = None;
+        let mut dry_run = false;
+        let mut silent = false;
+        let mut allow_mass_removal = false;
+        let mut positional = Vec::new();
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| usage(format!("{arg} needs a value")))
+            };
+
+            match arg.as_str() {
+                "--config" => config = value()?.into(),
+                "--day" => day = Some(parse_day(&value()?)?),
+                "--from" => from = Some(parse_day(&value()?)?),
+                "--to" => to = Some(parse_day(&value()?)?),
+                "--parser" => parser = Some(value()?),
+                "--dry-run" => dry_run = true,
+                "--silent" => silent = true,
+                "--allow-mass-removal" => allow_mass_removal = true,
+                "-h" | "--help" => return Err(usage("")),
+                flag if flag.starts_with("--") => {
+                    return Err(usage(format!("unknown flag {flag}")))
+                }
+                _ => positional.push(arg),
+            }
+        }
+
+        let positional: Vec<_> = positional.iter().map(String::as_str).collect();
+        let command = match positional.as_slice() {
+            [] => Command::Run,
+            // config path alone is how the bot has always been started
+            [path] if !["diff", "parse"].contains(path) => {
+                config = path.into();
+                Command::Run
+            }
+            ["diff", "export", output] => Command::DiffExport {
+                output: output.into(),
+                day,
+            },
+            ["diff", old, new] => Command::DiffCompare {
+                old: old.into(),
+                new: new.into(),
+            },
+            ["diff", ..] => {
+                return Err(usage(
+                    "diff needs either `export <snapshot>` or two snapshots",
+                ))
+            }
+            ["parse"] => {
+                let (Some(from), Some(to)) = (from, to) else {
+                    return Err(usage("parse needs both --from and --to"));
+                };
+                if to < from {
+                    return Err(usage("--to is before --from"));
+                }
+
+                Command::Parse(ParseArgs {
+                    from,
+                    to,
+                    parser: parser.clone().unwrap_or_else(|| "pjatk".to_owned()),
+                    dry_run,
+                    silent,
+                    allow_mass_removal,
+                })
+            }
+            _ => return Err(usage("unknown command")),
+        };
+
+        if day.is_some() && !matches!(command, Command::DiffExport { .. }) {
+            return Err(usage("--day is only used by `diff export`"));
+        }
+        let parse_flags = from.is_some()
+            || to.is_some()
+            || parser.is_some()
+            || dry_run
+            || silent
+            || allow_mass_removal;
+        if parse_flags && !matches!(command, Command::Parse(_)) {
+            return Err(usage(
+                "--from, --to, --parser, --dry-run, --silent and --allow-mass-removal are only used by `parse`",
+            ));
+        }
+
+        Ok(Self { config, command })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Args, UsageError> {
+        Args::parse(args.split_whitespace().map(str::to_owned).collect())
+    }
+
+    fn reason(args: &str) -> String {
+        parse(args).unwrap_err().reason
+    }
+
+    #[test]
+    fn bare_path_is_config_of_the_bot() {
+        let args = parse("/etc/schedulebot.toml").unwrap();
+        assert!(matches!(args.command, Command::Run));
+        assert_eq!(args.config, PathBuf::from("/etc/schedulebot.toml"));
+
+        let args = parse("").unwrap();
+        assert!(matches!(args.command, Command::Run));
+        assert_eq!(args.config, PathBuf::from("config.toml"));
+
+        // command names are never taken as a config path
+        assert_eq!(reason("parse"), "parse needs both --from and --to");
+        assert!(reason("diff").starts_with("diff needs"));
+        assert_eq!(reason("bot.toml extra"), "unknown command");
+    }
+
+    #[test]
+    fn parse_takes_its_flags() {
+        let args = parse(
+            "parse --from 2024-10-14 --to 2024-10-20 --parser ics --dry-run --config bot.toml",
+        )
+        .unwrap();
+
+        let Command::Parse(parse) = args.command else {
+            panic!("{:?}", args.command);
+        };
+        assert_eq!(parse.from, NaiveDate::from_ymd_opt(2024, 10, 14).unwrap());
+        assert_eq!(parse.to, NaiveDate::from_ymd_opt(2024, 10, 20).unwrap());
+        assert_eq!(parse.parser, "ics");
+        assert!(parse.dry_run && !parse.silent && !parse.allow_mass_removal);
+        assert_eq!(args.config, PathBuf::from("bot.toml"));
+    }
+
+    #[test]
+    fn flags_of_other_commands_are_rejected() {
+        for args in [
+            "--dry-run",
+            "diff old.json new.json --from 2024-10-14",
+            "diff export out.json --silent",
+            "bot.toml --parser pjatk",
+        ] {
+            assert!(reason(args).contains("only used by `parse`"), "{args}");
+        }
+
+        for args in [
+            "--day 2024-10-14",
+            "diff old.json new.json --day 2024-10-14",
+            "parse --from 2024-10-14 --to 2024-10-14 --day 2024-10-14",
+        ] {
+            assert_eq!(
+                reason(args),
+                "--day is only used by `diff export`",
+                "{args}"
+            );
+        }
+
+        assert!(parse("diff export out.json --day 2024-10-14").is_ok());
+    }
+
+    #[test]
+    fn parse_range_is_checked() {
+        assert_eq!(
+            reason("parse --from 2024-10-20 --to 2024-10-14"),
+            "--to is before --from"
+        );
+        assert_eq!(
+            reason("parse --from 2024-10-14"),
+            "parse needs both --from and --to"
+        );
+        assert_eq!(
+            reason("parse --from 14.10.2024 --to 2024-10-14"),
+            "'14.10.2024' isn't a date"
+        );
+        assert_eq!(reason("parse --from"), "--from needs a value");
+        assert_eq!(reason("parse --force"), "unknown flag --force");
+
+        // a single day is a valid range
+        assert!(parse("parse --from 2024-10-14 --to 2024-10-14").is_ok());
+    }
+}
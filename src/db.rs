@@ -3,14 +3,18 @@ use std::{collections::HashSet, hash::Hash};
 use chrono::{DateTime, NaiveTime, TimeDelta, TimeZone, Utc};
 use chrono_tz::Tz;
 use eyre::OptionExt;
+use futures::TryStreamExt;
 use mongodb::Collection;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    parsing::types::{Class, Group},
+    parsing::types::{self, Class, Group, StudyMode},
     Config,
 };
 
+pub mod migrations;
+pub mod snapshot;
+
 #[derive(
     Serialize,
     Deserialize,
@@ -38,16 +42,211 @@ impl Language {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq, Eq)]
+/// How long before the class user is reminded about it
+#[derive(Serialize, Deserialize, Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct NotificationConstraint(pub std::time::Duration);
 
+#[derive(thiserror::Error, Debug)]
+pub enum OffsetError {
+    #[error("'{0}' isn't an offset like 15m, 1h30m or 1d")]
+    Malformed(String),
+    #[error(
+        "offset should be from 1 minute to {} days",
+        NotificationConstraint::MAX_DAYS
+    )]
+    OutOfRange,
+    #[error(
+        "at most {} reminders can be set",
+        NotificationConstraint::MAX_PER_USER
+    )]
+    TooMany,
+}
+
+impl NotificationConstraint {
+    pub const MAX_DAYS: u64 = 7;
+    pub const MAX_PER_USER: usize = 5;
+
+    fn from_minutes(minutes: u64) -> Result<Self, OffsetError> {
+        if minutes == 0 || minutes > Self::MAX_DAYS * 24 * 60 {
+            return Err(OffsetError::OutOfRange);
+        }
+
+        Ok(Self(std::time::Duration::from_secs(minutes * 60)))
+    }
+
+    /// Reminders separated by spaces or commas, e.g. "15m, 1h30m 1d"
+    pub fn parse_list(raw: &str) -> Result<HashSet<Self>, OffsetError> {
+        let constraints = raw
+            .split(|char: char| char == ',' || char.is_whitespace())
+            .filter(|offset| !offset.is_empty())
+            .map(str::parse)
+            .collect::<Result<HashSet<_>, _>>()?;
+
+        match constraints.len() > Self::MAX_PER_USER {
+            true => Err(OffsetError::TooMany),
+            false => Ok(constraints),
+        }
+    }
+}
+
+// days, hours and minutes in this order, each at most once;
+// bare number is taken as minutes
+impl std::str::FromStr for NotificationConstraint {
+    type Err = OffsetError;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        let malformed = || OffsetError::Malformed(raw.to_owned());
+        let lowercase = raw.trim().to_lowercase();
+
+        if !lowercase.is_empty() && lowercase.chars().all(|char| char.is_ascii_digit()) {
+            let minutes: u64 = lowercase.parse().map_err(|_| malformed())?;
+            return Self::from_minutes(minutes);
+        }
+
+        let mut minutes = 0u64;
+        let mut rest = lowercase.as_str();
+        let mut units = ['d', 'h', 'm'].as_slice();
+
+        while !rest.is_empty() {
+            let digits = rest
+                .find(|char: char| !char.is_ascii_digit())
+                .ok_or_else(malformed)?;
+            let number: u64 = rest[..digits].parse().map_err(|_| malformed())?;
+
+            let unit = rest[digits..].chars().next().ok_or_else(malformed)?;
+            let position = units
+                .iter()
+                .position(|known| *known == unit)
+                .ok_or_else(malformed)?;
+            units = &units[position + 1..];
+
+            let unit_minutes = match unit {
+                'd' => 24 * 60,
+                'h' => 60,
+                _ => 1,
+            };
+            minutes = number
+                .checked_mul(unit_minutes)
+                .and_then(|part| part.checked_add(minutes))
+                .ok_or(OffsetError::OutOfRange)?;
+
+            rest = &rest[digits + unit.len_utf8()..];
+        }
+
+        match lowercase.is_empty() {
+            true => Err(malformed()),
+            false => Self::from_minutes(minutes),
+        }
+    }
+}
+
+/// Same form as parsed, e.g. "1h30m"
+impl std::fmt::Display for NotificationConstraint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let minutes = self.0.as_secs() / 60;
+        let (days, hours, minutes) = (minutes / (24 * 60), minutes / 60 % 24, minutes % 60);
+
+        if days > 0 {
+            write!(f, "{days}d")?;
+        }
+        if hours > 0 {
+            write!(f, "{hours}h")?;
+        }
+        if minutes > 0 || (days == 0 && hours == 0) {
+            write!(f, "{minutes}m")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Daily window in [`crate::BOT_TIMEZONE`] user doesn't want to be disturbed in,
+/// `start` later than `end` means it spans midnight
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuietHours {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("'{0}' isn't a window like 23:00-07:00")]
+pub struct QuietHoursError(String);
+
+impl QuietHours {
+    /// End of the window `at` falls into, `None` if it's outside of it
+    pub fn end_after(&self, at: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let time = at.with_timezone(&crate::BOT_TIMEZONE).time();
+
+        let inside = match self.start <= self.end {
+            true => self.start <= time && time < self.end,
+            false => self.start <= time || time < self.end,
+        };
+
+        inside.then(|| crate::notifications::next_local_time(self.end, None, at))
+    }
+}
+
+impl std::str::FromStr for QuietHours {
+    type Err = QuietHoursError;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        let malformed = || QuietHoursError(raw.to_owned());
+        let time =
+            |raw: &str| NaiveTime::parse_from_str(raw.trim(), "%H:%M").map_err(|_| malformed());
+
+        let (start, end) = raw.split_once('-').ok_or_else(malformed)?;
+        let (start, end) = (time(start)?, time(end)?);
+
+        match start == end {
+            true => Err(malformed()),
+            false => Ok(Self { start, end }),
+        }
+    }
+}
+
+/// Same form as parsed, e.g. "23:00-07:00"
+impl std::fmt::Display for QuietHours {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}-{}",
+            self.start.format("%H:%M"),
+            self.end.format("%H:%M")
+        )
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Role {
     User,
     BetaTester,
     Admin,
 }
-use bson::{oid::ObjectId, serde_helpers::chrono_datetime_as_bson_datetime};
+use bson::{
+    oid::ObjectId,
+    serde_helpers::{chrono_datetime_as_bson_datetime, chrono_datetime_as_bson_datetime_optional},
+};
+
+/// Narrows classes of user's groups, empty filter accepts everything
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClassFilter {
+    pub study_mode: Option<StudyMode>,
+    pub language: Option<types::Language>,
+}
+
+impl ClassFilter {
+    pub fn matches(&self, class: &Class) -> bool {
+        // classes with unknown mode or language are never filtered out
+        fn accepts<T: PartialEq>(wanted: &Option<T>, actual: &Option<T>) -> bool {
+            match (wanted, actual) {
+                (Some(wanted), Some(actual)) => wanted == actual,
+                _ => true,
+            }
+        }
+
+        accepts(&self.study_mode, &class.study_mode) && accepts(&self.language, &class.language)
+    }
+}
 
 pub type UserID = teloxide::types::ChatId;
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -60,15 +259,59 @@ pub struct User {
     pub groups: Vec<Group>,
     pub language: Language,
     pub constraints: HashSet<NotificationConstraint>,
+    #[serde(default)]
+    pub filter: ClassFilter,
+    /// time of day in [`crate::BOT_TIMEZONE`] the list of classes
+    /// for the day is sent at, `None` if user doesn't want it
+    #[serde(default)]
+    pub digest_time: Option<NaiveTime>,
+    /// same for the preview of the coming week, sent on Sundays
+    #[serde(default)]
+    pub weekly_digest_time: Option<NaiveTime>,
+    /// classes changed after it are highlighted in the next preview
+    #[serde(default, with = "chrono_datetime_as_bson_datetime_optional")]
+    pub last_weekly_digest: Option<DateTime<Utc>>,
+    /// notifications are held back during them
+    #[serde(default)]
+    pub quiet_hours: Option<QuietHours>,
+}
+
+/// What is sent when a notification fires
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationKind {
+    /// reminder about `related_class`
+    #[default]
+    ClassStart,
+    /// list of user's classes for the day, see [`User::digest_time`]
+    DailyDigest,
+    /// classes of the coming week, see [`User::weekly_digest_time`]
+    WeeklyDigest,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Notification {
     pub related_user: ObjectId,
-    pub related_class: ObjectId,
+    /// `None` for notifications which aren't about a single class
+    pub related_class: Option<ObjectId>,
     pub related_user_id: UserID,
     #[serde(with = "chrono_datetime_as_bson_datetime")]
     pub fire_date: DateTime<Utc>,
+    #[serde(default)]
+    pub kind: NotificationKind,
+    /// time of day the digest was asked for, the fire date may be off it
+    /// on the day clocks are moved forward
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local_time: Option<NaiveTime>,
+}
+
+/// Message which came during user's quiet hours, sent once they are over
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeferredMessage {
+    pub user_id: UserID,
+    pub content: String,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub send_at: DateTime<Utc>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -109,6 +352,10 @@ impl Model for User {
     const COLLECTION_NAME: &'static str = "users";
 }
 
+impl Model for DeferredMessage {
+    const COLLECTION_NAME: &'static str = "deferred_messages";
+}
+
 pub trait Model {
     const COLLECTION_NAME: &'static str;
 }
@@ -126,9 +373,191 @@ pub fn create_range_query<T: TimeZone>(
     mongodb::bson::doc! {"range.start": {"$gt": bson::DateTime::from(start_point), "$lt": bson::DateTime::from(end)}}
 }
 
+/// Changes of classes of `groups` since `since`, newest first
+pub async fn recent_changes(
+    coll: &Collection<types::ClassChange>,
+    groups: &[Group],
+    since: DateTime<Utc>,
+) -> eyre::Result<Vec<types::ClassChange>> {
+    let codes: Vec<_> = groups.iter().map(|group| group.code.as_str()).collect();
+
+    let changes = coll
+        .find(mongodb::bson::doc! {
+            "groups": {"$in": codes},
+            "changed_at": {"$gte": bson::DateTime::from(since)},
+        })
+        .sort(mongodb::bson::doc! {"changed_at": -1})
+        .await?
+        .try_collect()
+        .await?;
+
+    Ok(changes)
+}
+
 pub async fn load_database(config: &Config) -> eyre::Result<mongodb::Database> {
     let mongo_session = mongodb::Client::with_uri_str(&config.mongodb_uri).await?;
     let db = mongo_session.database(&config.database_name);
 
     Ok(db)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constraint_round_trips() {
+        let cases = [
+            ("15m", "15m"),
+            ("1h30m", "1h30m"),
+            ("1d", "1d"),
+            ("90", "1h30m"),
+            ("1H", "1h"),
+            (" 2d3h ", "2d3h"),
+            ("60m", "1h"),
+            ("7d", "7d"),
+        ];
+
+        for (raw, shown) in cases {
+            let constraint: NotificationConstraint = raw.parse().unwrap();
+            assert_eq!(constraint.to_string(), shown, "{raw}");
+            assert_eq!(shown.parse::<NotificationConstraint>().unwrap(), constraint);
+        }
+    }
+
+    #[test]
+    fn constraint_is_rejected() {
+        // units out of order or repeated, and numbers which don't fit at all
+        for raw in [
+            "",
+            "m",
+            "1.5h",
+            "15s",
+            "h1",
+            "1m1h",
+            "1h1h",
+            "30m1d",
+            "1h 30m",
+            "99999999999999999999m",
+        ] {
+            let result = raw.parse::<NotificationConstraint>();
+            assert!(matches!(result, Err(OffsetError::Malformed(_))), "{raw}");
+        }
+
+        // overflowing multiplication is out of range too
+        for raw in ["0", "0m", "7d1m", "10081", "18446744073709551615d"] {
+            let result = raw.parse::<NotificationConstraint>();
+            assert!(matches!(result, Err(OffsetError::OutOfRange)), "{raw}");
+        }
+    }
+
+    #[test]
+    fn constraint_list_is_limited() {
+        let list = NotificationConstraint::parse_list("15m, 1h 15").unwrap();
+        assert_eq!(list.len(), 2);
+
+        let err = NotificationConstraint::parse_list("1m 2m 3m 4m 5m 6m").unwrap_err();
+        assert!(matches!(err, OffsetError::TooMany));
+    }
+
+    fn class(study_mode: Option<StudyMode>, language: Option<types::Language>) -> Class {
+        let start = Utc.with_ymd_and_hms(2024, 10, 14, 8, 0, 0).unwrap();
+
+        Class {
+            class_id: "1".to_owned(),
+            name: "Bazy danych".to_owned(),
+            code: "BYT".to_owned(),
+            kind: types::ClassKind::Lecture,
+            lecturers: Vec::new(),
+            range: types::TimeRange {
+                start,
+                end: start + TimeDelta::minutes(90),
+            },
+            place: types::ClassPlace::Online,
+            groups: Vec::new(),
+            study_mode,
+            language,
+            source: "pjatk".to_owned(),
+        }
+    }
+
+    #[test]
+    fn filter_keeps_classes_it_can_not_tell() {
+        use types::Language::{English, Polish};
+
+        let filter = ClassFilter {
+            study_mode: Some(StudyMode::OnSite),
+            language: Some(English),
+        };
+
+        assert!(filter.matches(&class(Some(StudyMode::OnSite), Some(English))));
+        assert!(!filter.matches(&class(Some(StudyMode::Online), Some(English))));
+        assert!(!filter.matches(&class(Some(StudyMode::OnSite), Some(Polish))));
+
+        // joint lectures and classes of unrecognized groups
+        assert!(filter.matches(&class(Some(StudyMode::OnSite), None)));
+        assert!(filter.matches(&class(None, None)));
+        assert!(!filter.matches(&class(None, Some(Polish))));
+
+        let empty = ClassFilter::default();
+        assert!(empty.matches(&class(Some(StudyMode::PartTime), Some(Polish))));
+    }
+
+    fn warsaw(day: (i32, u32, u32), time: (u32, u32)) -> DateTime<Utc> {
+        crate::BOT_TIMEZONE
+            .with_ymd_and_hms(day.0, day.1, day.2, time.0, time.1, 0)
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    #[test]
+    fn overnight_quiet_hours_end_next_morning() {
+        let quiet: QuietHours = "23:00-07:00".parse().unwrap();
+
+        assert_eq!(
+            quiet.end_after(warsaw((2024, 10, 14), (23, 30))),
+            Some(warsaw((2024, 10, 15), (7, 0)))
+        );
+        assert_eq!(
+            quiet.end_after(warsaw((2024, 10, 15), (6, 59))),
+            Some(warsaw((2024, 10, 15), (7, 0)))
+        );
+        assert_eq!(quiet.end_after(warsaw((2024, 10, 15), (7, 0))), None);
+        assert_eq!(quiet.end_after(warsaw((2024, 10, 15), (22, 59))), None);
+    }
+
+    #[test]
+    fn same_day_quiet_hours_end_same_day() {
+        let quiet: QuietHours = "12:00-14:30".parse().unwrap();
+
+        assert_eq!(
+            quiet.end_after(warsaw((2024, 10, 14), (12, 0))),
+            Some(warsaw((2024, 10, 14), (14, 30)))
+        );
+        assert_eq!(quiet.end_after(warsaw((2024, 10, 14), (11, 59))), None);
+        assert_eq!(quiet.end_after(warsaw((2024, 10, 14), (23, 0))), None);
+    }
+
+    #[test]
+    fn quiet_hours_end_in_local_time_across_clock_changes() {
+        let quiet: QuietHours = "23:00-07:00".parse().unwrap();
+
+        // the night clocks go forward is an hour shorter
+        let spring = quiet.end_after(warsaw((2025, 3, 29), (23, 0))).unwrap();
+        assert_eq!(spring.to_rfc3339(), "2025-03-30T05:00:00+00:00");
+        assert_eq!(spring - warsaw((2025, 3, 29), (23, 0)), TimeDelta::hours(7));
+
+        // and the one they go back an hour longer
+        let autumn = quiet.end_after(warsaw((2024, 10, 26), (23, 0))).unwrap();
+        assert_eq!(autumn.to_rfc3339(), "2024-10-27T06:00:00+00:00");
+        assert_eq!(
+            autumn - warsaw((2024, 10, 26), (23, 0)),
+            TimeDelta::hours(9)
+        );
+
+        // window ending in the skipped hour ends right after the jump, not a day later
+        let skipped: QuietHours = "01:00-02:30".parse().unwrap();
+        let end = skipped.end_after(warsaw((2025, 3, 30), (1, 30))).unwrap();
+        assert_eq!(end.to_rfc3339(), "2025-03-30T01:30:00+00:00");
+    }
+}
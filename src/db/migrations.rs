@@ -0,0 +1,173 @@
+use bson::{doc, serde_helpers::chrono_datetime_as_bson_datetime, Document};
+use chrono::{DateTime, Utc};
+use futures::{future::BoxFuture, TryStreamExt};
+use mongodb::{options::IndexOptions, Database, IndexModel};
+use serde::{Deserialize, Serialize};
+use slog::Logger;
+
+use crate::parsing::{
+    manager::Data,
+    pjatk::deduct,
+    types::{Class, ClassChange},
+};
+
+use super::{Model, Notification};
+
+/// Migration which was applied to the database, each one runs only once
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AppliedMigration {
+    pub name: String,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub applied_at: DateTime<Utc>,
+}
+
+impl Model for AppliedMigration {
+    const COLLECTION_NAME: &'static str = "migrations";
+}
+
+type Migration = fn(&Database) -> BoxFuture<'_, eyre::Result<()>>;
+
+// append only, applied in this order
+const MIGRATIONS: &[(&str, Migration)] = &[
+    (
+        "0001_structured_lecturers_and_rooms",
+        structured_lecturers_and_rooms,
+    ),
+    ("0002_class_sources", class_sources),
+    ("0003_notification_kinds", notification_kinds),
+    ("0004_held_removals", held_removals),
+    ("0005_class_changes_ttl", class_changes_ttl),
+];
+
+/// History is kept well past the longest `/changes` window
+const CLASS_CHANGES_RETENTION: std::time::Duration =
+    std::time::Duration::from_secs(90 * 24 * 60 * 60);
+
+pub async fn run(db: &Database, logger: &Logger) -> eyre::Result<()> {
+    let applied = db.collection::<AppliedMigration>(AppliedMigration::COLLECTION_NAME);
+
+    for (name, migration) in MIGRATIONS {
+        if applied.find_one(doc! {"name": name}).await?.is_some() {
+            continue;
+        }
+
+        slog::info!(logger, "migration.applying"; "name" => name);
+        migration(db).await?;
+
+        applied
+            .insert_one(AppliedMigration {
+                name: name.to_string(),
+                applied_at: Utc::now(),
+            })
+            .await?;
+    }
+
+    Ok(())
+}
+
+// `lecturer` string becomes `lecturers` list, `place.room` string becomes `Room`
+fn structured_lecturers_and_rooms(db: &Database) -> BoxFuture<'_, eyre::Result<()>> {
+    Box::pin(async move {
+        let classes = db.collection::<Document>(Class::COLLECTION_NAME);
+
+        let mut old_classes = classes.find(doc! {"lecturer": {"$type": "string"}}).await?;
+
+        while let Some(class) = old_classes.try_next().await? {
+            let lecturers = deduct::parse_lecturers(class.get_str("lecturer")?);
+
+            let mut set = doc! {"lecturers": bson::to_bson(&lecturers)?};
+            if let Ok(room) = class
+                .get_document("place")
+                .and_then(|place| place.get_str("room"))
+            {
+                set.insert("place.room", bson::to_bson(&deduct::parse_room(room))?);
+            }
+
+            classes
+                .update_one(
+                    doc! {"_id": class.get_object_id("_id")?},
+                    doc! {"$set": set, "$unset": {"lecturer": ""}},
+                )
+                .await?;
+        }
+
+        Ok(())
+    })
+}
+
+// everything stored so far came from the PJATK plan
+fn class_sources(db: &Database) -> BoxFuture<'_, eyre::Result<()>> {
+    Box::pin(async move {
+        db.collection::<Document>(Class::COLLECTION_NAME)
+            .update_many(
+                doc! {"source": {"$exists": false}},
+                doc! {"$set": {"source": "pjatk"}},
+            )
+            .await?;
+
+        Ok(())
+    })
+}
+
+// notifications used to be only class reminders, they are looked up by whole
+// document, so the kind has to be there for them to be found again
+fn notification_kinds(db: &Database) -> BoxFuture<'_, eyre::Result<()>> {
+    Box::pin(async move {
+        db.collection::<Document>(Notification::COLLECTION_NAME)
+            .update_many(
+                doc! {"kind": {"$exists": false}},
+                doc! {"$set": {"kind": "class_start"}},
+            )
+            .await?;
+
+        Ok(())
+    })
+}
+
+// a single `pending_mass_removal` day becomes a list of held days
+fn held_removals(db: &Database) -> BoxFuture<'_, eyre::Result<()>> {
+    Box::pin(async move {
+        let datas = db.collection::<Document>(Data::COLLECTION_NAME);
+
+        let mut pending = datas
+            .find(doc! {"pending_mass_removal": {"$type": "string"}})
+            .await?;
+        while let Some(data) = pending.try_next().await? {
+            datas
+                .update_one(
+                    doc! {"_id": data.get_object_id("_id")?},
+                    doc! {"$addToSet": {"held_removals": data.get_str("pending_mass_removal")?}},
+                )
+                .await?;
+        }
+
+        datas
+            .update_many(
+                doc! {"pending_mass_removal": {"$exists": true}},
+                doc! {"$unset": {"pending_mass_removal": ""}},
+            )
+            .await?;
+
+        Ok(())
+    })
+}
+
+// history is only ever read for recent days, older records are dropped by MongoDB
+fn class_changes_ttl(db: &Database) -> BoxFuture<'_, eyre::Result<()>> {
+    Box::pin(async move {
+        let index = IndexModel::builder()
+            .keys(doc! {"changed_at": 1})
+            .options(
+                IndexOptions::builder()
+                    .expire_after(CLASS_CHANGES_RETENTION)
+                    .build(),
+            )
+            .build();
+
+        db.collection::<Document>(ClassChange::COLLECTION_NAME)
+            .create_index(index)
+            .await?;
+
+        Ok(())
+    })
+}
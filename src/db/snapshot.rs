@@ -0,0 +1,294 @@
+use std::{collections::BTreeMap, io::Write, path::Path};
+
+use bson::doc;
+use chrono::NaiveDate;
+use futures::TryStreamExt;
+use mongodb::Database;
+
+use crate::parsing::{
+    manager::day_start,
+    types::{ChangeKind, Class, ClassChange},
+};
+
+use super::Model;
+
+/// Dumps classes, of a single day if given, ordered by start and id
+/// so two dumps of the same schedule are byte for byte equal.
+/// Written as a JSON array if `path` ends with `.json`, one class per line otherwise
+pub async fn export(db: &Database, day: Option<NaiveDate>, path: &Path) -> eyre::Result<usize> {
+    let coll = db.collection::<Class>(Class::COLLECTION_NAME);
+
+    let query = match day {
+        Some(day) => crate::db::create_range_query(&day_start(day)?, None),
+        None => doc! {},
+    };
+
+    let mut classes: Vec<Class> = coll.find(query).await?.try_collect().await?;
+    classes.sort_by(|a, b| {
+        (a.range.start, &a.class_id, &a.source).cmp(&(b.range.start, &b.class_id, &b.source))
+    });
+
+    let is_array = path
+        .extension()
+        .is_some_and(|extension| extension == "json");
+    tokio::fs::write(path, render(&classes, is_array)?).await?;
+
+    Ok(classes.len())
+}
+
+fn render(classes: &[Class], is_array: bool) -> eyre::Result<Vec<u8>> {
+    // relaxed extended json keeps dates readable and still converts back to bson
+    let classes = classes
+        .iter()
+        .map(|class| Ok(bson::to_bson(class)?.into_relaxed_extjson()))
+        .collect::<eyre::Result<Vec<_>>>()?;
+
+    let mut raw = Vec::new();
+    if is_array {
+        serde_json::to_writer_pretty(&mut raw, &classes)?;
+        writeln!(raw)?;
+    } else {
+        for class in &classes {
+            serde_json::to_writer(&mut raw, class)?;
+            writeln!(raw)?;
+        }
+    }
+
+    Ok(raw)
+}
+
+/// Reads a snapshot written by [`export`], in either form
+pub async fn load(path: &Path) -> eyre::Result<Vec<Class>> {
+    let raw = tokio::fs::read_to_string(path).await?;
+
+    let values: Vec<serde_json::Value> = match raw.trim_start().starts_with('[') {
+        true => serde_json::from_str(&raw)?,
+        false => raw
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(serde_json::from_str)
+            .collect::<Result<_, _>>()?,
+    };
+
+    values
+        .into_iter()
+        .map(|value| Ok(bson::from_bson(bson::Bson::try_from(value)?)?))
+        .collect()
+}
+
+/// Changes from `old` to `new` snapshot, classes are matched by their
+/// source id the same way the parser matches them with the database
+pub fn compare(old: Vec<Class>, new: Vec<Class>) -> Vec<ClassChange> {
+    let key = |class: &Class| (class.source.clone(), class.class_id.clone());
+
+    let mut old: BTreeMap<_, _> = old.into_iter().map(|class| (key(&class), class)).collect();
+    let mut changes = Vec::new();
+
+    for class in new {
+        match old.remove(&key(&class)) {
+            Some(before) => {
+                let diff = before.diff(&class);
+                if !diff.is_empty() {
+                    changes.push(ClassChange::modified(before, class, diff));
+                }
+            }
+            None => changes.push(ClassChange::added(class)),
+        }
+    }
+    changes.extend(old.into_values().map(ClassChange::removed));
+
+    changes.sort_by_cached_key(|change| {
+        change
+            .class()
+            .map(|class| (class.range.start, class.class_id.clone()))
+    });
+    changes
+}
+
+fn format_change(change: &ClassChange, class: &Class) -> String {
+    let sign = match change.kind {
+        ChangeKind::Added => '+',
+        ChangeKind::Removed => '-',
+        ChangeKind::Modified => '~',
+    };
+
+    let local = |time: chrono::DateTime<chrono::Utc>| time.with_timezone(&crate::BOT_TIMEZONE);
+    let mut line = format!(
+        "{sign} {}-{} {} ({}) [{}]",
+        local(class.range.start).format("%H:%M"),
+        local(class.range.end).format("%H:%M"),
+        class.name,
+        class.kind,
+        class.class_id
+    );
+
+    if let (Some(before), ChangeKind::Modified) = (&change.before, change.kind) {
+        let fields: Vec<_> = change.diff.iter().map(|field| field.to_string()).collect();
+        line += &format!(" {}", fields.join(", "));
+
+        if before.range != class.range {
+            line += &format!(
+                ", was {}-{}",
+                local(before.range.start).format("%Y-%m-%d %H:%M"),
+                local(before.range.end).format("%H:%M")
+            );
+        }
+    }
+
+    line
+}
+
+/// Human readable report grouped by day and then by group,
+/// classes of several groups are listed under each of them
+pub fn report(changes: &[ClassChange]) -> String {
+    let mut by_day: BTreeMap<NaiveDate, BTreeMap<&str, Vec<String>>> = BTreeMap::new();
+
+    for change in changes {
+        let Some(class) = change.class() else {
+            continue;
+        };
+        let day = class
+            .range
+            .start
+            .with_timezone(&crate::BOT_TIMEZONE)
+            .date_naive();
+
+        let by_group = by_day.entry(day).or_default();
+        let line = format_change(change, class);
+        match change.groups.is_empty() {
+            true => by_group.entry("-").or_default().push(line),
+            false => {
+                for group in &change.groups {
+                    by_group.entry(&group.code).or_default().push(line.clone());
+                }
+            }
+        }
+    }
+
+    let count = |kind| changes.iter().filter(|change| change.kind == kind).count();
+    let mut report = String::new();
+
+    for (day, by_group) in by_day {
+        report += &format!("{day} ({})\n", day.format("%A"));
+        for (group, lines) in by_group {
+            report += &format!("  {group}\n");
+            for line in lines {
+                report += &format!("    {line}\n");
+            }
+        }
+    }
+
+    report += &format!(
+        "added {}, removed {}, modified {}\n",
+        count(ChangeKind::Added),
+        count(ChangeKind::Removed),
+        count(ChangeKind::Modified)
+    );
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{TimeZone, Utc};
+
+    use crate::parsing::types::{ClassKind, ClassPlace, Group, TimeRange};
+
+    use super::*;
+
+    fn class(class_id: &str, hour: u32, groups: &[&str]) -> Class {
+        let start = Utc.with_ymd_and_hms(2024, 10, 14, hour, 0, 0).unwrap();
+
+        Class {
+            class_id: class_id.to_owned(),
+            name: format!("Class {class_id}"),
+            code: String::new(),
+            kind: ClassKind::Lecture,
+            lecturers: Vec::new(),
+            range: TimeRange {
+                start,
+                end: start + chrono::TimeDelta::minutes(90),
+            },
+            place: ClassPlace::Online,
+            groups: groups
+                .iter()
+                .map(|code| Group {
+                    code: (*code).to_owned(),
+                })
+                .collect(),
+            study_mode: None,
+            language: None,
+            source: "pjatk".to_owned(),
+        }
+    }
+
+    // written the way `export` writes them, one snapshot in each form
+    async fn roundtrip(name: &str, classes: &[Class], is_array: bool) -> Vec<Class> {
+        let path = std::env::temp_dir().join(format!("snapshot-{}-{name}", std::process::id()));
+        tokio::fs::write(&path, render(classes, is_array).unwrap())
+            .await
+            .unwrap();
+
+        let loaded = load(&path).await.unwrap();
+        tokio::fs::remove_file(&path).await.unwrap();
+        loaded
+    }
+
+    #[tokio::test]
+    async fn compare_reports_added_removed_and_modified() {
+        let mut moved = class("2", 8, &["WIs I.1"]);
+        moved.range.start += chrono::TimeDelta::hours(2);
+        moved.range.end += chrono::TimeDelta::hours(2);
+        // same id from another source is another class
+        let mut other_source = class("1", 12, &[]);
+        other_source.source = "ics".to_owned();
+
+        let old = vec![
+            class("1", 6, &["WIs I.1", "WIs I.2"]),
+            class("2", 8, &["WIs I.1"]),
+            class("3", 12, &["WIs I.2"]),
+        ];
+        let new = vec![class("3", 12, &["WIs I.2"]), moved, other_source];
+
+        let old = roundtrip("old.json", &old, true).await;
+        let new = roundtrip("new.jsonl", &new, false).await;
+        let changes = compare(old, new);
+
+        let kinds: Vec<_> = changes
+            .iter()
+            .map(|change| (change.kind, change.class_id.as_str()))
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                (ChangeKind::Removed, "1"),
+                (ChangeKind::Modified, "2"),
+                (ChangeKind::Added, "1"),
+            ]
+        );
+
+        assert_eq!(
+            report(&changes),
+            "\
+2024-10-14 (Monday)
+  -
+    + 14:00-15:30 Class 1 (lecture) [1]
+  WIs I.1
+    - 08:00-09:30 Class 1 (lecture) [1]
+    ~ 12:00-13:30 Class 2 (lecture) [2] time, was 2024-10-14 10:00-11:30
+  WIs I.2
+    - 08:00-09:30 Class 1 (lecture) [1]
+added 1, removed 1, modified 1
+"
+        );
+    }
+
+    #[test]
+    fn unchanged_snapshots_have_no_changes() {
+        let classes = vec![class("1", 6, &[]), class("2", 8, &[])];
+
+        let changes = compare(classes.clone(), classes);
+        assert!(changes.is_empty());
+        assert_eq!(report(&changes), "added 0, removed 0, modified 0\n");
+    }
+}
@@ -1,11 +1,11 @@
 use std::convert::Infallible;
 
 use mongodb::Database;
-use parsing::pjatk::Parser;
 use slog::Logger;
 use tokio::task::JoinSet;
 
 pub mod bot;
+pub mod cli;
 pub mod db;
 pub mod notifications;
 pub mod parsing;
@@ -71,7 +71,14 @@ extern crate rust_i18n;
 pub struct Config {
     mongodb_uri: String,
     database_name: String,
-    pjatk: parsing::manager::Config,
+    /// parsers to run, see [`parsing::registry::Entry`]
+    #[serde(default)]
+    parsers: Vec<parsing::registry::Entry>,
+    /// older single-parser form, becomes a `pjatk` entry of `parsers`
+    #[serde(default)]
+    pjatk: Option<parsing::manager::Config>,
+    #[serde(default)]
+    pjatk_parser: parsing::pjatk::Config,
     telegram: bot::BotConfig,
 
     notifications_manager: notifications::manager::Config,
@@ -83,36 +90,63 @@ i18n!("locales", fallback = "en");
 
 #[tokio::main]
 async fn main() -> eyre::Result<()> {
-    let logger = setup_logger();
-    let config = load_config()?;
+    let args = cli::Args::from_env()?;
+
+    // comparing snapshots doesn't need the database, nor the config
+    if let cli::Command::DiffCompare { old, new } = &args.command {
+        let changes = db::snapshot::compare(
+            db::snapshot::load(old).await?,
+            db::snapshot::load(new).await?,
+        );
+        print!("{}", db::snapshot::report(&changes));
+        return Ok(());
+    }
+
+    let config = load_config(&args.config)?;
     let db = db::load_database(config).await?;
 
+    if let cli::Command::DiffExport { output, day } = &args.command {
+        let count = db::snapshot::export(&db, *day, output).await?;
+        println!("{count} classes written to {}", output.display());
+        return Ok(());
+    }
+
+    let logger = setup_logger();
+
+    if let cli::Command::Parse(parse) = &args.command {
+        db::migrations::run(&db, &logger).await?;
+        return backfill(config, &db, &logger, parse).await;
+    }
+
     let _log_guard = slog_scope::set_global_logger(logger.clone());
     slog_stdlog::init_with_level(log::Level::Info)?;
     slog::info!(logger, "boot");
 
+    db::migrations::run(&db, &logger).await?;
+
     let (notifications_tx, notifications_rx) = kanal::unbounded_async();
 
     let (updates_tx, updates_rx) = kanal::unbounded_async();
 
-    let mut bot = bot::setup_bot(
-        config,
-        &logger,
-        &db,
-        notifications_rx,
-        Box::new(updates_tx.clone()),
-    );
-
-    let mut tasks = setup_tasks(
+    let (mut tasks, parser_controls) = setup_tasks(
         &db,
         &config,
         &logger,
         notifications_tx,
-        updates_tx,
+        updates_tx.clone(),
         updates_rx,
     )
     .await?;
 
+    let mut bot = bot::setup_bot(
+        config,
+        &logger,
+        &db,
+        notifications_rx,
+        Box::new(updates_tx),
+        parser_controls,
+    );
+
     tokio::select! {
         Some(tasks) = tasks.join_next() => {
             // we only care about error
@@ -134,15 +168,19 @@ async fn setup_tasks(
     config: &'static Config,
     logger: &Logger,
     notifications_tx: impl channels::Tx<notifications::NotificationEvents> + Clone,
-    updates_tx: impl channels::Tx<notifications::UpdateEvents>,
+    updates_tx: impl channels::Tx<notifications::UpdateEvents> + Clone,
     updates_rx: impl channels::Rx<notifications::UpdateEvents>,
-) -> eyre::Result<JoinSet<Result<eyre::Result<Infallible>, tokio::task::JoinError>>> {
+) -> eyre::Result<(
+    JoinSet<Result<eyre::Result<Infallible>, tokio::task::JoinError>>,
+    parsing::control::Controls,
+)> {
     let mut handle_set = JoinSet::new();
 
-    let pjatk = Parser::new();
-    let parser_manager = parsing::manager::ParserManager::new(&db, pjatk, &config.pjatk, &logger);
-
-    handle_set.spawn(parser_manager.work(updates_tx));
+    let (parsers, parser_controls) =
+        parsing::registry::start_all(&config.parsers, db, logger, updates_tx)?;
+    for parser in parsers {
+        handle_set.spawn(parser);
+    }
 
     let notifications_manager = notifications::manager::NotificationManager::new(
         &config.notifications_manager,
@@ -161,7 +199,83 @@ async fn setup_tasks(
 
     handle_set.spawn(notifications_propagator.work(notifications_tx));
 
-    Ok(handle_set)
+    Ok((handle_set, parser_controls))
+}
+
+// parses days once without the bot running, changes are told to users
+// the same way the bot would do it unless `silent`
+async fn backfill(
+    config: &'static Config,
+    db: &Database,
+    logger: &Logger,
+    &cli::ParseArgs {
+        from,
+        to,
+        ref parser,
+        dry_run,
+        silent,
+        allow_mass_removal,
+    }: &cli::ParseArgs,
+) -> eyre::Result<()> {
+    let Some(entry) = config.parsers.iter().find(|entry| entry.name() == parser) else {
+        let known: Vec<_> = config.parsers.iter().map(|entry| entry.name()).collect();
+        eyre::bail!(
+            "parser '{parser}' isn't configured, known are: {}",
+            known.join(", ")
+        );
+    };
+
+    let (days, delta) = entry
+        .backfill(db, logger, from, to, dry_run, allow_mass_removal)
+        .await?;
+
+    // the logger writes to stdout, so the report goes apart from it
+    eprint!("{}", db::snapshot::report(&delta.changes()));
+    eprintln!(
+        "{days} days parsed, {} reservations added{}",
+        delta.added_reservations.len(),
+        if dry_run { ", nothing was written" } else { "" }
+    );
+    if !delta.held_removals.is_empty() {
+        let held: Vec<_> = delta
+            .held_removals
+            .iter()
+            .map(|held| format!("{} ({} classes)", held.day, held.removed))
+            .collect();
+        eprintln!(
+            "removals held back on {}, run again with --allow-mass-removal to apply them",
+            held.join(", ")
+        );
+    }
+
+    if dry_run || silent {
+        return Ok(());
+    }
+    let Some(events) = delta.into_events() else {
+        return Ok(());
+    };
+
+    let notifications_manager =
+        notifications::manager::NotificationManager::new(&config.notifications_manager, db, logger);
+    let bot = bot::new_bot(&config.telegram);
+    // nobody is there to handle user updates
+    let (updates_tx, _) = kanal::unbounded_async();
+    let state = bot::setup_state(
+        config,
+        logger,
+        db,
+        bot,
+        Box::new(updates_tx),
+        Default::default(),
+    );
+
+    for event in events {
+        if let Some(notification) = notifications_manager.handle_message(event).await? {
+            bot::notifications_sender::send_notification(&state, notification).await?;
+        }
+    }
+
+    Ok(())
 }
 
 fn setup_logger() -> slog::Logger {
@@ -177,10 +291,15 @@ fn setup_logger() -> slog::Logger {
     let logger = builder.build().unwrap();
     logger
 }
-fn load_config() -> eyre::Result<&'static Config> {
-    let config_file = std::env::args().nth(1).unwrap_or("config.toml".to_string());
-
-    let config: Config = toml::from_str(std::fs::read_to_string(config_file)?.as_ref())?;
+fn load_config(config_file: &std::path::Path) -> eyre::Result<&'static Config> {
+    let mut config: Config = toml::from_str(std::fs::read_to_string(config_file)?.as_ref())?;
+
+    if let Some(manager) = config.pjatk.take() {
+        config.parsers.push(parsing::registry::Entry {
+            manager,
+            kind: parsing::registry::Kind::Pjatk(std::mem::take(&mut config.pjatk_parser)),
+        });
+    }
 
     let config = Box::leak(Box::new(config));
     Ok(config)
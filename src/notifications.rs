@@ -1,10 +1,11 @@
 use std::collections::HashSet;
 
 use bson::oid::ObjectId;
+use chrono::{DateTime, Datelike, Days, NaiveDate, NaiveTime, TimeDelta, TimeZone, Utc, Weekday};
 
 use crate::{
     db::{Notification, User, UserID, OID},
-    parsing::types::Class,
+    parsing::types::{Class, ClassField, Reservation},
 };
 
 pub enum NotificationEvent {
@@ -12,17 +13,78 @@ pub enum NotificationEvent {
         class: Class,
         affected_users: HashSet<UserID>,
     },
+    ClassModified {
+        class: Class,
+        diff: Vec<ClassField>,
+        affected_users: HashSet<UserID>,
+    },
+    /// class appeared in the schedule shortly before it takes place
+    ClassAdded {
+        class: Class,
+        affected_users: HashSet<UserID>,
+    },
     Scheduled {
         class: Class,
         user_id: UserID,
     },
+    /// time has come to send user the list of today's classes
+    DailyDigest {
+        user_id: UserID,
+    },
+    /// same for classes of the coming week
+    WeeklyDigest {
+        user_id: UserID,
+    },
+    ReservationAnnounced {
+        reservation: Reservation,
+        affected_users: HashSet<UserID>,
+    },
+    ParserAlert {
+        parser: &'static str,
+        failures: u32,
+        error: String,
+        admins: HashSet<UserID>,
+    },
+    MassRemovalAlert {
+        parser: &'static str,
+        day: NaiveDate,
+        removed: usize,
+        admins: HashSet<UserID>,
+    },
 }
 pub enum UpdateEvent {
     ClassRemoved {
         class: OID<Class>,
     },
+    /// `announce` is false for classes of days parsed for the first time,
+    /// which are new only to the database
     ClassAdded {
         class: OID<Class>,
+        announce: bool,
+    },
+    /// Same class (by source id) was returned with different data,
+    /// `new` keeps the database id of `old`
+    ClassModified {
+        old: Box<OID<Class>>,
+        new: OID<Class>,
+        diff: Vec<ClassField>,
+    },
+    ReservationAdded {
+        reservation: OID<Reservation>,
+    },
+
+    /// Parser has failed `failures` times in a row
+    ParserFailing {
+        parser: &'static str,
+        failures: u32,
+        error: String,
+    },
+
+    /// Parsed day would remove `removed` classes at once, so it was left as it was
+    MassRemovalHeld {
+        parser: &'static str,
+        day: NaiveDate,
+        removed: usize,
     },
 
     /// User constrains were changed or created in some way
@@ -33,13 +95,46 @@ pub enum UpdateEvent {
 pub type NotificationEvents = smallvec::SmallVec<[NotificationEvent; 32]>;
 pub type UpdateEvents = smallvec::SmallVec<[UpdateEvent; 32]>;
 
+/// Students plan their week on Sunday
+pub const WEEKLY_DIGEST_DAY: Weekday = Weekday::Sun;
+
+/// First moment after `after` when clocks in [`crate::BOT_TIMEZONE`] show `time`,
+/// only on `weekday` if given
+pub fn next_local_time(
+    time: NaiveTime,
+    weekday: Option<Weekday>,
+    after: DateTime<Utc>,
+) -> DateTime<Utc> {
+    let today = after.with_timezone(&crate::BOT_TIMEZONE).date_naive();
+
+    (0..=14)
+        .map(|days| today + Days::new(days))
+        .filter(|day| weekday.is_none_or(|weekday| day.weekday() == weekday))
+        .filter_map(|day| {
+            let local = day.and_time(time);
+            // the time is skipped on the day clocks are moved forward,
+            // then it's the same moment the clocks show an hour later
+            crate::BOT_TIMEZONE
+                .from_local_datetime(&local)
+                .earliest()
+                .or_else(|| {
+                    crate::BOT_TIMEZONE
+                        .from_local_datetime(&(local + TimeDelta::hours(1)))
+                        .earliest()
+                })
+        })
+        .map(|fire_date| fire_date.with_timezone(&Utc))
+        .find(|fire_date| *fire_date > after)
+        .unwrap_or(after + TimeDelta::days(1))
+}
+
 pub mod manager;
 
 pub mod propagator {
     use std::convert::Infallible;
 
     use bson::doc;
-    use chrono::Utc;
+    use chrono::{DateTime, Utc};
     use futures::StreamExt;
     use mongodb::Collection;
     use serde::Deserialize;
@@ -48,17 +143,39 @@ pub mod propagator {
 
     use crate::{
         channels,
-        db::{Model, Notification},
+        db::{Model, Notification, NotificationKind},
         parsing::types::Class,
     };
 
-    use super::{NotificationEvent, NotificationEvents};
+    use super::{next_local_time, NotificationEvent, NotificationEvents, WEEKLY_DIGEST_DAY};
 
     #[derive(Debug, Deserialize)]
     pub struct Config {
         pub poll_interval: std::time::Duration,
     }
 
+    /// Same digest once more, at the time user asked for
+    fn next_digest(notification: &Notification, now: DateTime<Utc>) -> Notification {
+        let weekday = match notification.kind {
+            NotificationKind::WeeklyDigest => Some(WEEKLY_DIGEST_DAY),
+            _ => None,
+        };
+
+        // digests stored before the time was kept only have their fire date,
+        // which is off it if they fired on the day clocks were moved forward
+        let time = notification.local_time.unwrap_or_else(|| {
+            notification
+                .fire_date
+                .with_timezone(&crate::BOT_TIMEZONE)
+                .time()
+        });
+
+        Notification {
+            fire_date: next_local_time(time, weekday, now),
+            ..notification.clone()
+        }
+    }
+
     pub struct Propagator {
         notifications: Collection<Notification>,
         classes: Collection<Class>,
@@ -85,6 +202,11 @@ pub mod propagator {
             while let Some(notification) = notifications.next().await {
                 let notification = notification?;
 
+                if notification.kind != NotificationKind::ClassStart {
+                    result.extend(self.fire_digest(notification).await?);
+                    continue;
+                }
+
                 let class = self
                     .classes
                     .find_one(doc! {"_id": &notification.related_class})
@@ -107,6 +229,30 @@ pub mod propagator {
             Ok(result)
         }
 
+        // digests repeat, so the next one is scheduled right away
+        async fn fire_digest(
+            &self,
+            notification: Notification,
+        ) -> eyre::Result<Option<NotificationEvent>> {
+            let local_fire_date = notification.fire_date.with_timezone(&crate::BOT_TIMEZONE);
+            self.notifications
+                .insert_one(next_digest(&notification, Utc::now()))
+                .await?;
+
+            // e.g. bot was down, yesterday's classes are of no use
+            let today = Utc::now().with_timezone(&crate::BOT_TIMEZONE).date_naive();
+            if local_fire_date.date_naive() != today {
+                slog::warn!(self.logger, "propagator.stale_digest"; "user" => ?notification.related_user_id);
+                return Ok(None);
+            }
+
+            let user_id = notification.related_user_id;
+            Ok(Some(match notification.kind {
+                NotificationKind::WeeklyDigest => NotificationEvent::WeeklyDigest { user_id },
+                _ => NotificationEvent::DailyDigest { user_id },
+            }))
+        }
+
         pub fn work(
             self,
             tx: impl channels::Tx<NotificationEvents>,
@@ -133,4 +279,71 @@ pub mod propagator {
             tokio::task::spawn(fut)
         }
     }
+
+    #[cfg(test)]
+    mod tests {
+        use chrono::{NaiveTime, TimeZone};
+
+        use super::*;
+
+        fn warsaw(day: (i32, u32, u32), time: (u32, u32)) -> DateTime<Utc> {
+            crate::BOT_TIMEZONE
+                .with_ymd_and_hms(day.0, day.1, day.2, time.0, time.1, 0)
+                .unwrap()
+                .with_timezone(&Utc)
+        }
+
+        fn digest(kind: NotificationKind, fire_date: DateTime<Utc>) -> Notification {
+            Notification {
+                related_user: bson::oid::ObjectId::new(),
+                related_class: None,
+                related_user_id: teloxide::types::ChatId(1),
+                fire_date,
+                kind,
+                local_time: NaiveTime::from_hms_opt(2, 30, 0),
+            }
+        }
+
+        #[test]
+        fn digest_returns_to_its_time_after_clocks_move_forward() {
+            // 02:30 is skipped on 2025-03-30, that digest comes at 03:30
+            let fired = digest(
+                NotificationKind::DailyDigest,
+                warsaw((2025, 3, 29), (2, 30)),
+            );
+            let shifted = next_digest(&fired, fired.fire_date);
+            assert_eq!(shifted.fire_date, warsaw((2025, 3, 30), (3, 30)));
+
+            let next = next_digest(&shifted, shifted.fire_date);
+            assert_eq!(next.fire_date, warsaw((2025, 3, 31), (2, 30)));
+            assert_eq!(next.local_time, fired.local_time);
+        }
+
+        #[test]
+        fn weekly_digest_returns_to_its_time_after_clocks_move_forward() {
+            let fired = digest(
+                NotificationKind::WeeklyDigest,
+                warsaw((2025, 3, 23), (2, 30)),
+            );
+            let shifted = next_digest(&fired, fired.fire_date);
+            assert_eq!(shifted.fire_date, warsaw((2025, 3, 30), (3, 30)));
+
+            let next = next_digest(&shifted, shifted.fire_date);
+            assert_eq!(next.fire_date, warsaw((2025, 4, 6), (2, 30)));
+        }
+
+        #[test]
+        fn digest_without_kept_time_follows_its_fire_date() {
+            let fired = Notification {
+                local_time: None,
+                ..digest(
+                    NotificationKind::DailyDigest,
+                    warsaw((2025, 3, 31), (21, 0)),
+                )
+            };
+
+            let next = next_digest(&fired, fired.fire_date);
+            assert_eq!(next.fire_date, warsaw((2025, 4, 1), (21, 0)));
+        }
+    }
 }
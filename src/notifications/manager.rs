@@ -1,7 +1,7 @@
 use std::{collections::HashSet, convert::Infallible, pin::Pin};
 
 use bson::{doc, oid::ObjectId};
-use chrono::{TimeDelta, Utc};
+use chrono::{NaiveDate, TimeDelta, Utc};
 use eyre::OptionExt;
 use futures::{Sink, Stream, StreamExt};
 use mongodb::{Collection, Database};
@@ -11,8 +11,11 @@ use smallvec::smallvec;
 
 use crate::{
     channels,
-    db::{Model, Notification, NotificationConstraint, OIDCollection, User, UserID, OID},
-    parsing::types::Class,
+    db::{
+        ClassFilter, Model, Notification, NotificationConstraint, NotificationKind, OIDCollection,
+        User, UserID, OID,
+    },
+    parsing::types::{Class, ClassField, Group, Reservation},
 };
 
 use super::{NotificationEvent, NotificationEvents, UpdateEvent, UpdateEvents};
@@ -20,6 +23,19 @@ use super::{NotificationEvent, NotificationEvents, UpdateEvent, UpdateEvents};
 #[derive(Debug, Deserialize)]
 pub struct Config {
     full_resync_interval: std::time::Duration,
+
+    /// Tell users about new reservations which mention their groups
+    #[serde(default)]
+    announce_reservations: bool,
+
+    /// Tell users about classes added to their schedule which start within this time,
+    /// zero turns it off
+    #[serde(default = "default_new_class_horizon")]
+    new_class_horizon: std::time::Duration,
+}
+
+fn default_new_class_horizon() -> std::time::Duration {
+    std::time::Duration::from_secs(7 * 24 * 60 * 60)
 }
 
 pub struct NotificationManager {
@@ -49,6 +65,7 @@ impl NotificationManager {
         let as_doc = mongodb::bson::to_document(&notification)?;
         self.notifications
             .find_one_and_replace(as_doc, notification)
+            .upsert(true)
             .await?;
         Ok(())
     }
@@ -69,7 +86,7 @@ impl NotificationManager {
             while let Some(user) = affected_users.next().await {
                 let user = user?;
 
-                if seen_users.contains(&user.id) {
+                if seen_users.contains(&user.id) || !user.data.filter.matches(&class.data) {
                     continue;
                 }
 
@@ -83,9 +100,11 @@ impl NotificationManager {
 
                     let notification = Notification {
                         related_user: user.id.clone(),
-                        related_class: class.id.clone(),
+                        related_class: Some(class.id.clone()),
                         fire_date: notification_time,
                         related_user_id: user.data.telegram_id,
+                        kind: NotificationKind::ClassStart,
+                        local_time: None,
                     };
                     slog::info!(self.logger, "handle_class_add.new_notification"; "notification" => ?notification);
 
@@ -98,20 +117,103 @@ impl NotificationManager {
         Ok(())
     }
 
-    async fn handle_class_removal(&self, class: OID<Class>) -> eyre::Result<NotificationEvent> {
+    async fn handle_class_announce(
+        &self,
+        class: OID<Class>,
+    ) -> eyre::Result<Option<NotificationEvent>> {
+        let now = Utc::now();
+        let horizon = now + TimeDelta::from_std(self.config.new_class_horizon)?;
+        if class.data.range.start < now || class.data.range.start > horizon {
+            return Ok(None);
+        }
+
+        let affected_users = self.class_users(&class.data).await?;
+        if affected_users.is_empty() {
+            return Ok(None);
+        }
+
+        slog::info!(self.logger, "handle_class_announce"; "class" => ?class);
+
+        Ok(Some(NotificationEvent::ClassAdded {
+            class: class.data,
+            affected_users,
+        }))
+    }
+
+    async fn affected_users(
+        &self,
+        groups: &[Group],
+        filter: impl Fn(&User) -> bool,
+    ) -> eyre::Result<HashSet<UserID>> {
         let mut final_users_affected = HashSet::new();
 
         // again, usually classes have a few groups
-        for class_group in class.data.groups.iter() {
+        for class_group in groups.iter() {
             let mut users_in_this_group =
-                self.users.find(doc! {"group": &class_group.code}).await?;
+                self.users.find(doc! {"groups": &class_group.code}).await?;
 
             while let Some(user) = users_in_this_group.next().await {
                 let user = user?;
-                final_users_affected.insert(user.data.telegram_id);
+                if filter(&user.data) {
+                    final_users_affected.insert(user.data.telegram_id);
+                }
             }
         }
 
+        Ok(final_users_affected)
+    }
+
+    async fn class_users(&self, class: &Class) -> eyre::Result<HashSet<UserID>> {
+        self.affected_users(&class.groups, |user| user.filter.matches(class))
+            .await
+    }
+
+    async fn admins(&self) -> eyre::Result<HashSet<UserID>> {
+        let mut admins = HashSet::new();
+
+        let mut admin_users = self.users.find(doc! {"role": "Admin"}).await?;
+        while let Some(admin) = admin_users.next().await {
+            admins.insert(admin?.data.telegram_id);
+        }
+
+        Ok(admins)
+    }
+
+    async fn handle_parser_failing(
+        &self,
+        parser: &'static str,
+        failures: u32,
+        error: String,
+    ) -> eyre::Result<NotificationEvent> {
+        slog::warn!(self.logger, "handle_parser_failing"; "parser" => parser, "failures" => failures);
+
+        Ok(NotificationEvent::ParserAlert {
+            parser,
+            failures,
+            error,
+            admins: self.admins().await?,
+        })
+    }
+
+    async fn handle_mass_removal_held(
+        &self,
+        parser: &'static str,
+        day: NaiveDate,
+        removed: usize,
+    ) -> eyre::Result<NotificationEvent> {
+        slog::warn!(self.logger, "handle_mass_removal_held"; "parser" => parser, "day" => day.to_string(), "removed" => removed);
+
+        Ok(NotificationEvent::MassRemovalAlert {
+            parser,
+            day,
+            removed,
+            admins: self.admins().await?,
+        })
+    }
+
+    async fn handle_class_removal(&self, class: OID<Class>) -> eyre::Result<NotificationEvent> {
+        let final_users_affected = self.class_users(&class.data).await?;
+
         slog::info!(self.logger, "handle_class_removal"; "class" => ?class);
 
         Ok(NotificationEvent::ClassDeleted {
@@ -120,6 +222,56 @@ impl NotificationManager {
         })
     }
 
+    async fn handle_class_modification(
+        &self,
+        old: Box<OID<Class>>,
+        new: OID<Class>,
+        diff: Vec<ClassField>,
+    ) -> eyre::Result<NotificationEvent> {
+        // reminders depend only on start time and on who attends the class
+        if diff.contains(&ClassField::Time) || diff.contains(&ClassField::Groups) {
+            self.notifications
+                .delete_many(doc! {"related_class": &new.id})
+                .await?;
+            self.handle_class_add(new.clone()).await?;
+        }
+
+        // users who have left the class due to group change should know too
+        let mut affected_users = self.class_users(&old.data).await?;
+        affected_users.extend(self.class_users(&new.data).await?);
+
+        slog::info!(self.logger, "handle_class_modification"; "class" => ?new, "diff" => ?diff);
+
+        Ok(NotificationEvent::ClassModified {
+            class: new.data,
+            diff,
+            affected_users,
+        })
+    }
+
+    async fn handle_reservation_add(
+        &self,
+        reservation: OID<Reservation>,
+    ) -> eyre::Result<Option<NotificationEvent>> {
+        if !self.config.announce_reservations || reservation.data.range.start < Utc::now() {
+            return Ok(None);
+        }
+
+        let affected_users = self
+            .affected_users(&reservation.data.groups, |_| true)
+            .await?;
+        if affected_users.is_empty() {
+            return Ok(None);
+        }
+
+        slog::info!(self.logger, "handle_reservation_add"; "reservation" => ?reservation);
+
+        Ok(Some(NotificationEvent::ReservationAnnounced {
+            reservation: reservation.data,
+            affected_users,
+        }))
+    }
+
     async fn full_resync(&self) -> eyre::Result<()> {
         let student_and_all_classes = [
             doc! {
@@ -163,7 +315,16 @@ impl NotificationManager {
                 continue;
             }
 
+            let filter: ClassFilter = match student.data.get("filter") {
+                Some(filter) => mongodb::bson::from_bson(filter.clone())?,
+                None => ClassFilter::default(),
+            };
+
             for class in classes {
+                if !filter.matches(&class.data) {
+                    continue;
+                }
+
                 if class.data.range.start < Utc::now() {
                     slog::warn!(self.logger, "full_resync.class_to_old"; );
                     continue;
@@ -180,9 +341,11 @@ impl NotificationManager {
 
                     let notification = Notification {
                         related_user: student.id.clone(),
-                        related_class: class.id.clone(),
+                        related_class: Some(class.id.clone()),
                         fire_date: new_time,
                         related_user_id: telegram_id,
+                        kind: NotificationKind::ClassStart,
+                        local_time: None,
                     };
 
                     let notification_doc = mongodb::bson::to_document(&notification)?;
@@ -204,10 +367,69 @@ impl NotificationManager {
         Ok(())
     }
 
+    // only digests user asked for, a pending one is kept as is
+    async fn schedule_digests(&self, user: &OID<User>) -> eyre::Result<()> {
+        let digests = [
+            (NotificationKind::DailyDigest, user.data.digest_time, None),
+            (
+                NotificationKind::WeeklyDigest,
+                user.data.weekly_digest_time,
+                Some(super::WEEKLY_DIGEST_DAY),
+            ),
+        ];
+
+        for (kind, time, weekday) in digests {
+            let Some(time) = time else {
+                continue;
+            };
+
+            let pending = self
+                .notifications
+                .find_one(doc! {"related_user": &user.id, "kind": bson::to_bson(&kind)?})
+                .await?;
+            if pending.is_some() {
+                continue;
+            }
+
+            let notification = Notification {
+                related_user: user.id,
+                related_class: None,
+                related_user_id: user.data.telegram_id,
+                fire_date: super::next_local_time(time, weekday, Utc::now()),
+                kind,
+                local_time: Some(time),
+            };
+            slog::info!(self.logger, "schedule_digest"; "notification" => ?notification);
+
+            self.notifications.insert_one(notification).await?;
+        }
+
+        Ok(())
+    }
+
+    // digests reschedule themselves when fired, this only catches
+    // the ones lost e.g. while the bot was down during the night
+    async fn resync_digests(&self) -> eyre::Result<()> {
+        let mut users = self
+            .users
+            .find(doc! {"$or": [
+                {"digest_time": {"$ne": null}},
+                {"weekly_digest_time": {"$ne": null}},
+            ]})
+            .await?;
+
+        while let Some(user) = users.next().await {
+            self.schedule_digests(&user?).await?;
+        }
+
+        Ok(())
+    }
+
     async fn handle_user_update(&self, user: &OID<User>) -> eyre::Result<()> {
         self.notifications
             .delete_many(doc! {"related_user": &user.id})
             .await?;
+        self.schedule_digests(user).await?;
 
         for group in user.data.groups.iter() {
             // don't care about collisions here because notifications are upserted
@@ -215,6 +437,10 @@ impl NotificationManager {
 
             while let Some(class) = affected_classes.next().await {
                 let class = class?;
+                if !user.data.filter.matches(&class.data) {
+                    continue;
+                }
+
                 for constraint in user.data.constraints.iter() {
                     let new_time =
                         class.data.range.start - TimeDelta::from_std(constraint.0.clone())?;
@@ -226,9 +452,11 @@ impl NotificationManager {
 
                     let notification = Notification {
                         related_user: user.id.clone(),
-                        related_class: class.id.clone(),
+                        related_class: Some(class.id.clone()),
                         fire_date: new_time,
                         related_user_id: user.data.telegram_id,
+                        kind: NotificationKind::ClassStart,
+                        local_time: None,
                     };
 
                     self.upsert_notification(notification).await?;
@@ -239,16 +467,47 @@ impl NotificationManager {
         Ok(())
     }
 
-    async fn handle_message(&self, msg: UpdateEvent) -> eyre::Result<Option<NotificationEvent>> {
+    /// Keeps reminders in sync with the event and tells who has to be notified about it, if anyone
+    pub async fn handle_message(
+        &self,
+        msg: UpdateEvent,
+    ) -> eyre::Result<Option<NotificationEvent>> {
         match msg {
             UpdateEvent::ClassRemoved { class } => {
                 return Ok(Some(self.handle_class_removal(class).await?));
             }
+            UpdateEvent::ParserFailing {
+                parser,
+                failures,
+                error,
+            } => {
+                return Ok(Some(
+                    self.handle_parser_failing(parser, failures, error).await?,
+                ));
+            }
+            UpdateEvent::MassRemovalHeld {
+                parser,
+                day,
+                removed,
+            } => {
+                return Ok(Some(
+                    self.handle_mass_removal_held(parser, day, removed).await?,
+                ));
+            }
             UpdateEvent::UserUpdate { user } => {
                 self.handle_user_update(&user).await?;
             }
-            UpdateEvent::ClassAdded { class } => {
-                self.handle_class_add(class).await?;
+            UpdateEvent::ClassAdded { class, announce } => {
+                self.handle_class_add(class.clone()).await?;
+                if announce {
+                    return self.handle_class_announce(class).await;
+                }
+            }
+            UpdateEvent::ClassModified { old, new, diff } => {
+                return Ok(Some(self.handle_class_modification(old, new, diff).await?));
+            }
+            UpdateEvent::ReservationAdded { reservation } => {
+                return self.handle_reservation_add(reservation).await;
             }
         }
 
@@ -261,6 +520,7 @@ impl NotificationManager {
         tx: impl channels::Tx<NotificationEvents>,
     ) -> eyre::Result<tokio::task::JoinHandle<eyre::Result<Infallible>>> {
         self.full_resync().await?;
+        self.resync_digests().await?;
         let fut = async move {
             loop {
                 tokio::select! {
@@ -1,19 +1,83 @@
-use types::Class;
+use types::{Class, QuarantinedClass, Reservation};
 
 pub trait IntoLocalized {
     fn localized(&self, locale: &str) -> &str;
 }
 
+#[derive(Debug, Default)]
+pub struct ParsedDay {
+    pub classes: Vec<Class>,
+    pub quarantined: Vec<QuarantinedClass>,
+    /// `None` if the parser doesn't see reservations,
+    /// stored ones are left untouched then
+    pub reservations: Option<Vec<Reservation>>,
+
+    /// source reported the day is the same as last time it was stored,
+    /// `classes` are empty and shouldn't be diffed
+    pub unchanged: bool,
+    /// stored through [`cache::ParseCache::store_day`] once the day is saved
+    pub fingerprint: Option<String>,
+}
+
+/// Source page doesn't look the way the parser expects anymore
+#[derive(thiserror::Error, Debug)]
+#[error("source layout has changed, '{selector}' didn't match")]
+pub struct LayoutChanged {
+    pub selector: String,
+    /// fragment in which the selector was looked up
+    pub html: String,
+    pub backtrace: String,
+}
+
 pub trait ScheduleParser: Send + Sync + 'static {
     const NAME: &'static str;
+
+    /// Tag of the classes this parser stores, parsers sharing a source
+    /// see the same classes and may cancel each other's
+    const SOURCE: &'static str = Self::NAME;
+
+    /// Whether stored class is expected to be returned by this parser,
+    /// classes it doesn't cover are never treated as removed
+    fn covers(&self, _class: &Class) -> bool {
+        true
+    }
+
     fn parse_day(
         &mut self,
         day: chrono::NaiveDate,
-    ) -> impl std::future::Future<Output = eyre::Result<Vec<Class>>> + Send;
+        cache: &cache::ParseCache,
+    ) -> impl std::future::Future<Output = eyre::Result<ParsedDay>> + Send;
+
+    /// Parses days from `start` to `end` inclusive, parsers which
+    /// can share work between days should override it
+    fn parse_range(
+        &mut self,
+        start: chrono::NaiveDate,
+        end: chrono::NaiveDate,
+        cache: &cache::ParseCache,
+    ) -> impl std::future::Future<Output = eyre::Result<Vec<(chrono::NaiveDate, ParsedDay)>>> + Send
+    {
+        async move {
+            let mut days = Vec::new();
+            for day in start.iter_days().take_while(|day| *day <= end) {
+                days.push((day, self.parse_day(day, cache).await?));
+            }
+
+            Ok(days)
+        }
+    }
 }
 
 pub mod types;
 
+pub mod cache;
+
 pub mod manager;
 
+pub mod control;
+
 pub mod pjatk;
+
+pub mod ics;
+
+pub mod registry;
@@ -0,0 +1,180 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use bson::{doc, serde_helpers::chrono_datetime_as_bson_datetime};
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+use mongodb::Collection;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+use crate::db::Model;
+
+#[derive(thiserror::Error, Debug)]
+pub enum CacheError {
+    #[error("cache query failed")]
+    Db(#[from] mongodb::error::Error),
+    #[error("can't serialize cached value")]
+    Serialize(#[from] bson::ser::Error),
+}
+
+/// Short hash used to detect changes of source data.
+///
+/// Only compared against values produced by the same binary in practice,
+/// a mismatch after toolchain upgrade merely causes a refetch
+pub fn fingerprint<T: Hash + ?Sized>(value: &T) -> String {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DayFingerprint {
+    pub parser: String,
+    pub day: NaiveDate,
+    pub fingerprint: String,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub updated_at: DateTime<Utc>,
+}
+
+impl Model for DayFingerprint {
+    const COLLECTION_NAME: &'static str = "day_fingerprints";
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CachedDetail {
+    pub parser: String,
+    pub key: String,
+    pub fingerprint: String,
+    pub value: bson::Bson,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub fetched_at: DateTime<Utc>,
+}
+
+impl Model for CachedDetail {
+    const COLLECTION_NAME: &'static str = "detail_cache";
+}
+
+struct Storage {
+    days: Collection<DayFingerprint>,
+    details: Collection<CachedDetail>,
+}
+
+/// Per-parser storage which lets a parser skip requests for data
+/// it has already seen. Entries older than `ttl` are treated as missing
+pub struct ParseCache {
+    parser: &'static str,
+    ttl: TimeDelta,
+    /// `None` when nothing is remembered, see [`ParseCache::disabled`]
+    storage: Option<Storage>,
+}
+
+impl ParseCache {
+    pub fn new(db: &mongodb::Database, parser: &'static str, ttl: std::time::Duration) -> Self {
+        Self {
+            parser,
+            ttl: TimeDelta::from_std(ttl).unwrap_or(TimeDelta::max_value()),
+            storage: Some(Storage {
+                days: db.collection(DayFingerprint::COLLECTION_NAME),
+                details: db.collection(CachedDetail::COLLECTION_NAME),
+            }),
+        }
+    }
+
+    /// Cache which misses every lookup and drops whatever is stored,
+    /// for parsing without a database or without leaving traces
+    pub fn disabled(parser: &'static str) -> Self {
+        Self {
+            parser,
+            ttl: TimeDelta::zero(),
+            storage: None,
+        }
+    }
+
+    fn is_fresh(&self, date: DateTime<Utc>) -> bool {
+        Utc::now() - date < self.ttl
+    }
+
+    pub async fn is_day_unchanged(
+        &self,
+        day: NaiveDate,
+        fingerprint: &str,
+    ) -> Result<bool, CacheError> {
+        let Some(storage) = &self.storage else {
+            return Ok(false);
+        };
+
+        let stored = storage
+            .days
+            .find_one(doc! {"parser": self.parser, "day": day.to_string()})
+            .await?;
+
+        Ok(stored.is_some_and(|stored| {
+            stored.fingerprint == fingerprint && self.is_fresh(stored.updated_at)
+        }))
+    }
+
+    pub async fn store_day(&self, day: NaiveDate, fingerprint: String) -> Result<(), CacheError> {
+        let Some(storage) = &self.storage else {
+            return Ok(());
+        };
+
+        let entry = DayFingerprint {
+            parser: self.parser.to_owned(),
+            day,
+            fingerprint,
+            updated_at: Utc::now(),
+        };
+
+        storage
+            .days
+            .find_one_and_replace(doc! {"parser": self.parser, "day": day.to_string()}, entry)
+            .upsert(true)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn detail<T: DeserializeOwned>(
+        &self,
+        key: &str,
+        fingerprint: &str,
+    ) -> Result<Option<T>, CacheError> {
+        let Some(storage) = &self.storage else {
+            return Ok(None);
+        };
+
+        let stored = storage
+            .details
+            .find_one(doc! {"parser": self.parser, "key": key})
+            .await?
+            .filter(|stored| stored.fingerprint == fingerprint && self.is_fresh(stored.fetched_at));
+
+        // entries stored in an older shape are simply fetched again
+        Ok(stored.and_then(|stored| bson::from_bson(stored.value).ok()))
+    }
+
+    pub async fn store_detail<T: Serialize>(
+        &self,
+        key: &str,
+        fingerprint: &str,
+        value: &T,
+    ) -> Result<(), CacheError> {
+        let Some(storage) = &self.storage else {
+            return Ok(());
+        };
+
+        let entry = CachedDetail {
+            parser: self.parser.to_owned(),
+            key: key.to_owned(),
+            fingerprint: fingerprint.to_owned(),
+            value: bson::to_bson(value)?,
+            fetched_at: Utc::now(),
+        };
+
+        storage
+            .details
+            .find_one_and_replace(doc! {"parser": self.parser, "key": key}, entry)
+            .upsert(true)
+            .await?;
+
+        Ok(())
+    }
+}
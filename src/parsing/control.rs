@@ -0,0 +1,63 @@
+use std::collections::BTreeMap;
+
+use chrono::NaiveDate;
+use tokio::sync::oneshot;
+
+/// What admins can ask a running parser to do
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Control {
+    /// Parse days from `start` to `end` inclusive right away, bypassing the cache.
+    /// Days which would lose too many classes are held back, unless `allow_mass_removal`
+    Reparse {
+        start: NaiveDate,
+        end: NaiveDate,
+        allow_mass_removal: bool,
+    },
+    Pause,
+    Resume,
+    Status,
+}
+
+#[derive(Debug)]
+pub struct ControlRequest {
+    pub control: Control,
+    pub reply: oneshot::Sender<ControlReply>,
+}
+
+#[derive(Debug)]
+pub enum ControlReply {
+    Reparsed {
+        days: usize,
+        added: usize,
+        removed: usize,
+        modified: usize,
+        /// days held back for removing too many classes
+        held: Vec<NaiveDate>,
+    },
+    Paused,
+    Resumed,
+    Status(ParserStatus),
+    Failed(String),
+}
+
+#[derive(Debug)]
+pub struct ParserStatus {
+    pub paused: bool,
+    pub last_day_parsed: Option<NaiveDate>,
+    pub consecutive_failures: u32,
+    pub held_removals: Vec<NaiveDate>,
+}
+
+/// Control channels of running parsers by their names
+pub type Controls = BTreeMap<&'static str, kanal::AsyncSender<ControlRequest>>;
+
+/// Requests are handled between parses, so the reply may take a while
+pub async fn send(
+    parser: &kanal::AsyncSender<ControlRequest>,
+    control: Control,
+) -> eyre::Result<ControlReply> {
+    let (reply, response) = oneshot::channel();
+    parser.send(ControlRequest { control, reply }).await?;
+
+    Ok(response.await?)
+}
@@ -0,0 +1,902 @@
+use std::{collections::HashSet, io::BufReader, path::PathBuf, str::FromStr, time::Instant};
+
+use chrono::{
+    DateTime, Datelike, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, TimeZone, Utc, Weekday,
+};
+use ical::{parser::ical::component::IcalEvent, property::Property};
+
+use super::{
+    cache::{self, ParseCache},
+    pjatk::deduct,
+    types::{Class, ClassKind, ClassPlace, Group, Lecturer, QuarantinedClass, TimeRange},
+    ParsedDay, ScheduleParser,
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum IcsError {
+    #[error("can't read calendar file")]
+    Io(#[from] std::io::Error),
+    #[error("can't download calendar")]
+    Http(#[from] reqwest::Error),
+    #[error("calendar is malformed")]
+    Malformed(#[from] ical::parser::ParserError),
+}
+
+/// Reason a single event couldn't be turned into a [`Class`]
+#[derive(thiserror::Error, Debug)]
+pub enum EventError {
+    #[error("event has no '{0}'")]
+    Missing(&'static str),
+    #[error("can't parse '{0}' as date or time")]
+    Time(String),
+    #[error("unknown time zone '{0}'")]
+    TimeZone(String),
+    #[error("can't parse '{0}' as duration")]
+    Duration(String),
+    #[error("unsupported recurrence rule '{0}'")]
+    Recurrence(String),
+}
+
+#[derive(Debug, PartialEq)]
+enum Frequency {
+    Daily,
+    Weekly,
+}
+
+/// Recurrence rule of a series, only the parts course calendars use
+#[derive(Debug, PartialEq)]
+struct Recurrence {
+    frequency: Frequency,
+    interval: u32,
+    count: Option<u32>,
+    /// last moment an occurrence may start at
+    until: Option<DateTime<Utc>>,
+    by_day: Vec<Weekday>,
+    week_start: Weekday,
+}
+
+fn weekday(raw: &str) -> Option<Weekday> {
+    Some(match raw {
+        "MO" => Weekday::Mon,
+        "TU" => Weekday::Tue,
+        "WE" => Weekday::Wed,
+        "TH" => Weekday::Thu,
+        "FR" => Weekday::Fri,
+        "SA" => Weekday::Sat,
+        "SU" => Weekday::Sun,
+        _ => return None,
+    })
+}
+
+impl FromStr for Recurrence {
+    type Err = EventError;
+
+    fn from_str(raw: &str) -> Result<Self, Self::Err> {
+        let unsupported = || EventError::Recurrence(raw.to_owned());
+
+        let mut frequency = None;
+        let mut rule = Recurrence {
+            frequency: Frequency::Daily,
+            interval: 1,
+            count: None,
+            until: None,
+            by_day: Vec::new(),
+            week_start: Weekday::Mon,
+        };
+
+        for part in raw.split(';').filter(|part| !part.is_empty()) {
+            let (key, value) = part.split_once('=').ok_or_else(unsupported)?;
+
+            match key {
+                "FREQ" => {
+                    frequency = Some(match value {
+                        "DAILY" => Frequency::Daily,
+                        "WEEKLY" => Frequency::Weekly,
+                        _ => return Err(unsupported()),
+                    })
+                }
+                "INTERVAL" => {
+                    rule.interval = value
+                        .parse()
+                        .ok()
+                        .filter(|interval| *interval > 0)
+                        .ok_or_else(unsupported)?
+                }
+                "COUNT" => rule.count = Some(value.parse().map_err(|_| unsupported())?),
+                "UNTIL" => rule.until = Some(parse_until(value)?),
+                // days with an ordinal, e.g. `1MO`, only make sense for monthly rules
+                "BYDAY" => {
+                    rule.by_day = value
+                        .split(',')
+                        .map(weekday)
+                        .collect::<Option<_>>()
+                        .ok_or_else(unsupported)?
+                }
+                "WKST" => rule.week_start = weekday(value).ok_or_else(unsupported)?,
+                _ => return Err(unsupported()),
+            }
+        }
+
+        rule.frequency = frequency.ok_or_else(unsupported)?;
+        Ok(rule)
+    }
+}
+
+impl Recurrence {
+    // ignores COUNT and UNTIL, see `has_occurrence`
+    fn matches(&self, first: NaiveDate, date: NaiveDate) -> bool {
+        if date < first {
+            return false;
+        }
+        // series always starts with its own start
+        if date == first {
+            return true;
+        }
+
+        let on_day = |days: &[Weekday]| days.is_empty() || days.contains(&date.weekday());
+        match self.frequency {
+            Frequency::Daily => {
+                on_day(&self.by_day) && (date - first).num_days() % self.interval as i64 == 0
+            }
+            Frequency::Weekly => {
+                let week = |date: NaiveDate| {
+                    let into_week = (date.weekday().num_days_from_monday() + 7
+                        - self.week_start.num_days_from_monday())
+                        % 7;
+                    date - TimeDelta::days(into_week as i64)
+                };
+                let weeks = (week(date) - week(first)).num_days() / 7;
+
+                let days = match self.by_day.is_empty() {
+                    true => vec![first.weekday()],
+                    false => self.by_day.clone(),
+                };
+                on_day(&days) && weeks % self.interval as i64 == 0
+            }
+        }
+    }
+
+    /// Whether the series started on `first` has an occurrence on `date`
+    fn has_occurrence(&self, first: NaiveDate, date: NaiveDate) -> bool {
+        if !self.matches(first, date) {
+            return false;
+        }
+        let Some(count) = self.count else {
+            return true;
+        };
+
+        // excluded occurrences count too, so every date up to this one is checked
+        let preceding = first
+            .iter_days()
+            .take_while(|day| *day <= date)
+            .filter(|day| self.matches(first, *day))
+            .count();
+        preceding <= count as usize
+    }
+}
+
+/// Where the calendar is read from
+#[derive(serde::Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum Location {
+    Path(PathBuf),
+    Url(String),
+}
+
+fn default_class_kind() -> String {
+    "Event".to_owned()
+}
+
+fn default_max_age() -> std::time::Duration {
+    std::time::Duration::from_secs(10 * 60)
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct Config {
+    #[serde(flatten)]
+    pub location: Location,
+
+    /// given to events without categories, so someone gets to see them
+    #[serde(default)]
+    pub groups: Vec<String>,
+
+    /// calendars don't tell lectures from seminars, shown as the kind of all events
+    #[serde(default = "default_class_kind")]
+    pub class_kind: String,
+
+    /// the calendar is fetched once for all days parsed within this time
+    #[serde(default = "default_max_age")]
+    pub max_age: std::time::Duration,
+}
+
+/// Imports classes from an iCalendar file, one `VEVENT` per class
+pub struct IcsParser {
+    location: &'static Location,
+    groups: Vec<Group>,
+    class_kind: String,
+    max_age: std::time::Duration,
+
+    client: reqwest::Client,
+    fetched: Option<(Instant, Vec<IcalEvent>)>,
+}
+
+impl IcsParser {
+    pub fn from_config(config: &'static Config) -> Self {
+        Self {
+            location: &config.location,
+            groups: config
+                .groups
+                .iter()
+                .map(|code| Group { code: code.clone() })
+                .collect(),
+            class_kind: config.class_kind.clone(),
+            max_age: config.max_age,
+            client: reqwest::Client::new(),
+            fetched: None,
+        }
+    }
+
+    async fn fetch(&self) -> Result<Vec<IcalEvent>, IcsError> {
+        let raw = match self.location {
+            Location::Path(path) => tokio::fs::read_to_string(path).await?,
+            Location::Url(url) => {
+                self.client
+                    .get(url)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .text()
+                    .await?
+            }
+        };
+
+        let mut events = Vec::new();
+        for calendar in ical::IcalParser::new(BufReader::new(raw.as_bytes())) {
+            events.extend(calendar?.events);
+        }
+
+        Ok(events)
+    }
+
+    async fn refresh(&mut self) -> Result<(), IcsError> {
+        let fresh = self
+            .fetched
+            .as_ref()
+            .is_some_and(|(fetched_at, _)| fetched_at.elapsed() < self.max_age);
+
+        if !fresh {
+            self.fetched = Some((Instant::now(), self.fetch().await?));
+        }
+
+        Ok(())
+    }
+
+    // classes of the event which start around `day`, a series gives its occurrences,
+    // except for the `overridden` ones which have an event of their own
+    fn deduct(
+        &self,
+        event: &IcalEvent,
+        day: NaiveDate,
+        overridden: &HashSet<(String, DateTime<Utc>)>,
+    ) -> Result<Vec<Class>, EventError> {
+        // cancelled occurrences are simply gone from the schedule
+        if text(event, "STATUS").is_some_and(|status| status.eq_ignore_ascii_case("CANCELLED")) {
+            return Ok(Vec::new());
+        }
+
+        let uid = text(event, "UID").ok_or(EventError::Missing("UID"))?;
+        let dtstart = property(event, "DTSTART").ok_or(EventError::Missing("DTSTART"))?;
+        let (start, all_day) = parse_time(dtstart)?;
+        let length = event_length(event, start, all_day)?;
+
+        let Some(rule) = text(event, "RRULE") else {
+            let class_id = event_id(event).ok_or(EventError::Missing("UID"))?;
+            let range = TimeRange {
+                start,
+                end: start + length,
+            };
+            return Ok(vec![self.class(event, class_id, range)?]);
+        };
+        let rule: Recurrence = rule.parse()?;
+
+        let raw_start = dtstart.value.as_deref().unwrap_or_default();
+        let (local_start, zone, _) = parse_local(raw_start, param(dtstart, "TZID"))?;
+        let excluded = exdates(event)?;
+
+        // occurrence may fall on a neighbouring day in the bot timezone
+        let candidates = [day.pred_opt(), Some(day), day.succ_opt()];
+
+        let mut classes = Vec::new();
+        for date in candidates.into_iter().flatten() {
+            if !rule.has_occurrence(local_start.date(), date) {
+                continue;
+            }
+
+            let local = date.and_time(local_start.time());
+            // e.g. the hour skipped by a DST change
+            let Some(start) = to_utc(local, zone) else {
+                continue;
+            };
+
+            let is_excluded = excluded
+                .iter()
+                .any(|&(excluded, excluded_zone, whole_day)| match whole_day {
+                    true => excluded.date() == date,
+                    false => to_utc(excluded, excluded_zone) == Some(start),
+                });
+            if rule.until.is_some_and(|until| start > until)
+                || is_excluded
+                || overridden.contains(&(uid.clone(), start))
+            {
+                continue;
+            }
+
+            // same as RECURRENCE-ID of an override, so both are matched as one class
+            let class_id = format!("{uid}/{}", format_local(local, zone, all_day));
+            let range = TimeRange {
+                start,
+                end: start + length,
+            };
+            classes.push(self.class(event, class_id, range)?);
+        }
+
+        Ok(classes)
+    }
+
+    fn class(
+        &self,
+        event: &IcalEvent,
+        class_id: String,
+        range: TimeRange,
+    ) -> Result<Class, EventError> {
+        let mut groups: Vec<_> = event
+            .properties
+            .iter()
+            .filter(|property| property.name == "CATEGORIES")
+            .filter_map(|property| property.value.as_deref())
+            .flat_map(split_list)
+            .map(|code| Group { code })
+            .collect();
+        if groups.is_empty() {
+            groups = self.groups.clone();
+        }
+
+        Ok(Class {
+            class_id,
+            name: text(event, "SUMMARY").ok_or(EventError::Missing("SUMMARY"))?,
+            code: String::new(),
+            kind: ClassKind::Other(self.class_kind.clone()),
+            lecturers: property(event, "ORGANIZER")
+                .and_then(organiser)
+                .into_iter()
+                .collect(),
+            range,
+            place: place(text(event, "LOCATION")),
+            study_mode: deduct::deduct_study_mode(&groups),
+            language: deduct::deduct_language(&groups),
+            groups,
+            source: String::new(),
+        })
+    }
+}
+
+fn property<'e>(event: &'e IcalEvent, name: &str) -> Option<&'e Property> {
+    event
+        .properties
+        .iter()
+        .find(|property| property.name == name)
+}
+
+fn param<'p>(property: &'p Property, name: &str) -> Option<&'p str> {
+    property
+        .params
+        .iter()
+        .flatten()
+        .find(|(key, _)| key == name)
+        .and_then(|(_, values)| values.first())
+        .map(String::as_str)
+}
+
+fn text(event: &IcalEvent, name: &str) -> Option<String> {
+    property(event, name)
+        .and_then(|property| property.value.as_deref())
+        .map(unescape)
+        .filter(|value| !value.trim().is_empty())
+}
+
+// text values escape `,` `;` `\` and newlines with a backslash
+fn unescape(raw: &str) -> String {
+    let mut unescaped = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+
+    while let Some(char) = chars.next() {
+        if char != '\\' {
+            unescaped.push(char);
+            continue;
+        }
+
+        match chars.next() {
+            Some('n' | 'N') => unescaped.push('\n'),
+            Some(escaped) => unescaped.push(escaped),
+            None => unescaped.push(char),
+        }
+    }
+
+    unescaped
+}
+
+// items of list values are separated by unescaped commas
+fn split_list(raw: &str) -> Vec<String> {
+    let mut items = Vec::new();
+    let mut item = String::new();
+    let mut chars = raw.chars();
+
+    while let Some(char) = chars.next() {
+        match char {
+            '\\' => item.extend(chars.next()),
+            ',' => items.push(std::mem::take(&mut item)),
+            _ => item.push(char),
+        }
+    }
+    items.push(item);
+
+    items
+        .into_iter()
+        .map(|item| item.trim().to_owned())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+// date or date-time as written, with the zone it's local to,
+// date-only values are whole days, returned flag tells about it
+fn parse_local(
+    raw: &str,
+    tzid: Option<&str>,
+) -> Result<(NaiveDateTime, chrono_tz::Tz, bool), EventError> {
+    let invalid = || EventError::Time(raw.to_owned());
+
+    // whole days are the ones of the university
+    if raw.len() == 8 {
+        let date = NaiveDate::parse_from_str(raw, "%Y%m%d").map_err(|_| invalid())?;
+        return Ok((date.and_time(NaiveTime::MIN), crate::BOT_TIMEZONE, true));
+    }
+
+    if let Some(utc) = raw.strip_suffix('Z') {
+        let time = NaiveDateTime::parse_from_str(utc, "%Y%m%dT%H%M%S").map_err(|_| invalid())?;
+        return Ok((time, chrono_tz::Tz::UTC, false));
+    }
+
+    let time = NaiveDateTime::parse_from_str(raw, "%Y%m%dT%H%M%S").map_err(|_| invalid())?;
+    // floating times are taken as local to the university
+    let timezone = match tzid {
+        Some(tzid) => {
+            chrono_tz::Tz::from_str(tzid).map_err(|_| EventError::TimeZone(tzid.to_owned()))?
+        }
+        None => crate::BOT_TIMEZONE,
+    };
+
+    Ok((time, timezone, false))
+}
+
+fn to_utc(local: NaiveDateTime, zone: chrono_tz::Tz) -> Option<DateTime<Utc>> {
+    zone.from_local_datetime(&local)
+        .earliest()
+        .map(|time| time.with_timezone(&Utc))
+}
+
+// written back the way calendars write it, e.g. in RECURRENCE-ID
+fn format_local(local: NaiveDateTime, zone: chrono_tz::Tz, all_day: bool) -> String {
+    let format = match (all_day, zone == chrono_tz::Tz::UTC) {
+        (true, _) => "%Y%m%d",
+        (false, true) => "%Y%m%dT%H%M%SZ",
+        (false, false) => "%Y%m%dT%H%M%S",
+    };
+
+    local.format(format).to_string()
+}
+
+fn parse_time(property: &Property) -> Result<(DateTime<Utc>, bool), EventError> {
+    let raw = property.value.as_deref().unwrap_or_default();
+    let (local, zone, all_day) = parse_local(raw, param(property, "TZID"))?;
+    let time = to_utc(local, zone).ok_or_else(|| EventError::Time(raw.to_owned()))?;
+
+    Ok((time, all_day))
+}
+
+// date-only UNTIL lets the whole day in
+fn parse_until(raw: &str) -> Result<DateTime<Utc>, EventError> {
+    let (local, zone, all_day) = parse_local(raw, None)?;
+    let until = match all_day {
+        true => to_utc(local + TimeDelta::days(1), zone).map(|end| end - TimeDelta::seconds(1)),
+        false => to_utc(local, zone),
+    };
+
+    until.ok_or_else(|| EventError::Time(raw.to_owned()))
+}
+
+// e.g. `PT1H30M`, `P1D` or `P2W`
+fn parse_duration(raw: &str) -> Option<TimeDelta> {
+    let (negative, unsigned) = match raw.strip_prefix('-') {
+        Some(unsigned) => (true, unsigned),
+        None => (false, raw.strip_prefix('+').unwrap_or(raw)),
+    };
+
+    let mut duration = TimeDelta::zero();
+    let mut number = String::new();
+    let mut in_time = false;
+    let mut has_value = false;
+
+    for char in unsigned.strip_prefix('P')?.chars() {
+        match char {
+            '0'..='9' => number.push(char),
+            'T' if !in_time && number.is_empty() => in_time = true,
+            unit => {
+                let value: i64 = number.parse().ok()?;
+                number.clear();
+
+                duration += match (unit, in_time) {
+                    ('W', false) => TimeDelta::try_weeks(value)?,
+                    ('D', false) => TimeDelta::try_days(value)?,
+                    ('H', true) => TimeDelta::try_hours(value)?,
+                    ('M', true) => TimeDelta::try_minutes(value)?,
+                    ('S', true) => TimeDelta::try_seconds(value)?,
+                    _ => return None,
+                };
+                has_value = true;
+            }
+        }
+    }
+
+    (has_value && number.is_empty()).then_some(if negative { -duration } else { duration })
+}
+
+// either DTEND or DURATION, whole days without them last a day
+fn event_length(
+    event: &IcalEvent,
+    start: DateTime<Utc>,
+    all_day: bool,
+) -> Result<TimeDelta, EventError> {
+    if let Some(end) = property(event, "DTEND") {
+        return Ok(parse_time(end)?.0 - start);
+    }
+
+    if let Some(duration) = property(event, "DURATION") {
+        let raw = duration.value.as_deref().unwrap_or_default();
+        return parse_duration(raw).ok_or_else(|| EventError::Duration(raw.to_owned()));
+    }
+
+    match all_day {
+        true => Ok(TimeDelta::days(1)),
+        false => Err(EventError::Missing("DTEND")),
+    }
+}
+
+// occurrences taken out of a series, there may be several in one property
+fn exdates(event: &IcalEvent) -> Result<Vec<(NaiveDateTime, chrono_tz::Tz, bool)>, EventError> {
+    event
+        .properties
+        .iter()
+        .filter(|property| property.name == "EXDATE")
+        .flat_map(|property| {
+            let raw = property.value.as_deref().unwrap_or_default();
+            raw.split(',')
+                .filter(|value| !value.is_empty())
+                .map(move |value| parse_local(value, param(property, "TZID")))
+        })
+        .collect()
+}
+
+// organiser is the closest calendars have to a lecturer, named as "First Last"
+fn organiser(property: &Property) -> Option<Lecturer> {
+    let name = param(property, "CN")?.trim_matches('"').trim();
+    let (first_name, last_name) = name.rsplit_once(' ').unwrap_or(("", name));
+
+    (!last_name.is_empty()).then(|| Lecturer {
+        first_name: first_name.trim().to_owned(),
+        last_name: last_name.to_owned(),
+    })
+}
+
+// events without a location or with a meeting link aren't held in a room
+fn place(location: Option<String>) -> ClassPlace {
+    match location {
+        Some(location)
+            if !location.starts_with("http") && !location.eq_ignore_ascii_case("online") =>
+        {
+            ClassPlace::OnSite {
+                room: deduct::parse_room(&location),
+            }
+        }
+        _ => ClassPlace::Online,
+    }
+}
+
+// overridden occurrences of a series share the uid
+fn event_id(event: &IcalEvent) -> Option<String> {
+    let uid = text(event, "UID")?;
+
+    Some(match text(event, "RECURRENCE-ID") {
+        Some(recurrence) => format!("{uid}/{recurrence}"),
+        None => uid,
+    })
+}
+
+// day of the event as written in the calendar, good enough to tell
+// which day a broken event belongs to
+fn event_day(event: &IcalEvent) -> Option<NaiveDate> {
+    let raw = property(event, "DTSTART")?.value.as_deref()?;
+    NaiveDate::parse_from_str(raw.get(..8)?, "%Y%m%d").ok()
+}
+
+fn quarantine(event: &IcalEvent, err: &EventError) -> QuarantinedClass {
+    QuarantinedClass {
+        parser: IcsParser::NAME.to_owned(),
+        source_id: event_id(event).unwrap_or_default(),
+        error: err.to_string(),
+        raw: event
+            .properties
+            .iter()
+            .map(|property| {
+                let value = property.value.clone().unwrap_or_default();
+                (property.name.clone(), bson::Bson::String(value))
+            })
+            .collect(),
+        detected_at: Utc::now(),
+    }
+}
+
+impl ScheduleParser for IcsParser {
+    const NAME: &'static str = "ics";
+
+    async fn parse_day(&mut self, day: NaiveDate, cache: &ParseCache) -> eyre::Result<ParsedDay> {
+        self.refresh().await?;
+        let events = self
+            .fetched
+            .as_ref()
+            .map(|(_, events)| events.as_slice())
+            .unwrap_or_default();
+
+        let on_day = |class: &Class| {
+            class
+                .range
+                .start
+                .with_timezone(&crate::BOT_TIMEZONE)
+                .date_naive()
+                == day
+        };
+
+        // occurrences of a series which were moved or changed have an event of their own
+        let overridden: HashSet<_> = events
+            .iter()
+            .filter_map(|event| {
+                let (recurrence, _) = parse_time(property(event, "RECURRENCE-ID")?).ok()?;
+                Some((text(event, "UID")?, recurrence))
+            })
+            .collect();
+
+        let mut classes = Vec::new();
+        let mut quarantined = Vec::new();
+
+        for event in events {
+            // series has no single day, so its failures are reported on any
+            let is_reported = property(event, "RRULE").is_some()
+                || event_day(event).is_none_or(|event_day| event_day == day);
+
+            match self.deduct(event, day, &overridden) {
+                Ok(deducted) => classes.extend(deducted.into_iter().filter(|class| on_day(class))),
+                Err(err) if is_reported => quarantined.push(quarantine(event, &err)),
+                Err(_) => (),
+            }
+        }
+
+        let fingerprint = cache::fingerprint(&classes);
+        if cache.is_day_unchanged(day, &fingerprint).await? {
+            return Ok(ParsedDay {
+                unchanged: true,
+                ..Default::default()
+            });
+        }
+
+        // calendars have no reservations
+        Ok(ParsedDay {
+            classes,
+            quarantined,
+            reservations: None,
+            unchanged: false,
+            fingerprint: Some(fingerprint),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // weekly lecture with one week off and one moved occurrence, and a workshop given by length
+    const CALENDAR: &str = "\
+BEGIN:VCALENDAR
+VERSION:2.0
+PRODID:-//pjatk//test//EN
+BEGIN:VEVENT
+UID:series@pjatk
+DTSTART;TZID=Europe/Warsaw:20241007T100000
+DTEND;TZID=Europe/Warsaw:20241007T113000
+RRULE:FREQ=WEEKLY;BYDAY=MO;UNTIL=20241104T235959Z
+EXDATE;TZID=Europe/Warsaw:20241021T100000
+SUMMARY:Bazy danych
+CATEGORIES:WIs I.1,WIs I.2
+ORGANIZER;CN=\"Jan Kowalski\":mailto:jan.kowalski@pjwstk.edu.pl
+LOCATION:A/2020
+END:VEVENT
+BEGIN:VEVENT
+UID:series@pjatk
+RECURRENCE-ID;TZID=Europe/Warsaw:20241028T100000
+DTSTART;TZID=Europe/Warsaw:20241028T120000
+DTEND;TZID=Europe/Warsaw:20241028T133000
+SUMMARY:Bazy danych
+CATEGORIES:WIs I.1,WIs I.2
+LOCATION:A/2020
+END:VEVENT
+BEGIN:VEVENT
+UID:workshop@pjatk
+DTSTART:20241014T120000Z
+DURATION:PT1H30M
+SUMMARY:Warsztaty\\, Rust
+LOCATION:https://teams.microsoft.com/l/meetup-join
+END:VEVENT
+END:VCALENDAR
+";
+
+    fn parser() -> IcsParser {
+        let config = Box::leak(Box::new(Config {
+            location: Location::Path(PathBuf::new()),
+            groups: Vec::new(),
+            class_kind: default_class_kind(),
+            max_age: std::time::Duration::MAX,
+        }));
+
+        let events = ical::IcalParser::new(BufReader::new(CALENDAR.as_bytes()))
+            .flat_map(|calendar| calendar.unwrap().events)
+            .collect();
+
+        let mut parser = IcsParser::from_config(config);
+        parser.fetched = Some((Instant::now(), events));
+        parser
+    }
+
+    async fn parse(day: (i32, u32, u32)) -> ParsedDay {
+        let day = NaiveDate::from_ymd_opt(day.0, day.1, day.2).unwrap();
+        let cache = ParseCache::disabled(IcsParser::NAME);
+
+        parser().parse_day(day, &cache).await.unwrap()
+    }
+
+    fn property(value: &str, tzid: Option<&str>) -> Property {
+        Property {
+            name: "DTSTART".to_owned(),
+            params: tzid.map(|tzid| vec![("TZID".to_owned(), vec![tzid.to_owned()])]),
+            value: Some(value.to_owned()),
+        }
+    }
+
+    #[test]
+    fn time_is_taken_in_its_zone() {
+        let utc = parse_time(&property("20241014T080000Z", None)).unwrap();
+        let zoned = parse_time(&property("20241014T100000", Some("Europe/London"))).unwrap();
+        let floating = parse_time(&property("20241014T100000", None)).unwrap();
+        let date = parse_time(&property("20241014", None)).unwrap();
+
+        assert_eq!(utc.0.to_rfc3339(), "2024-10-14T08:00:00+00:00");
+        assert_eq!(zoned.0.to_rfc3339(), "2024-10-14T09:00:00+00:00");
+        assert_eq!(floating.0.to_rfc3339(), "2024-10-14T08:00:00+00:00");
+        assert_eq!(date.0.to_rfc3339(), "2024-10-13T22:00:00+00:00");
+        assert_eq!(
+            [utc.1, zoned.1, floating.1, date.1],
+            [false, false, false, true]
+        );
+
+        assert!(matches!(
+            parse_time(&property("20241014T100000", Some("Mars/Olympus"))),
+            Err(EventError::TimeZone(_))
+        ));
+        assert!(matches!(
+            parse_time(&property("2024-10-14", None)),
+            Err(EventError::Time(_))
+        ));
+    }
+
+    #[test]
+    fn text_values_are_unescaped() {
+        assert_eq!(
+            unescape(r"Sala\, budynek A\;\npiętro 2\\"),
+            "Sala, budynek A;\npiętro 2\\"
+        );
+        assert_eq!(unescape(r"trailing\"), r"trailing\");
+
+        assert_eq!(split_list(r"WIs I.1,WIs\, I.2"), ["WIs I.1", "WIs, I.2"]);
+        assert!(split_list(" , ").is_empty());
+    }
+
+    #[test]
+    fn meeting_links_are_online() {
+        assert!(matches!(place(None), ClassPlace::Online));
+        assert!(matches!(
+            place(Some("Online".to_owned())),
+            ClassPlace::Online
+        ));
+        assert!(matches!(
+            place(Some("https://teams.microsoft.com/l/meetup-join".to_owned())),
+            ClassPlace::Online
+        ));
+        assert!(matches!(
+            place(Some("A/2020".to_owned())),
+            ClassPlace::OnSite { .. }
+        ));
+    }
+
+    #[test]
+    fn durations_are_parsed() {
+        assert_eq!(parse_duration("PT1H30M"), Some(TimeDelta::minutes(90)));
+        assert_eq!(parse_duration("P1DT12H"), Some(TimeDelta::hours(36)));
+        assert_eq!(parse_duration("P2W"), Some(TimeDelta::weeks(2)));
+        assert_eq!(parse_duration("-PT15M"), Some(TimeDelta::minutes(-15)));
+
+        for invalid in ["", "P", "PT", "PT1H30", "P1H", "1H", "PT1D"] {
+            assert_eq!(parse_duration(invalid), None, "{invalid}");
+        }
+    }
+
+    #[test]
+    fn unsupported_rules_are_rejected() {
+        for rule in [
+            "FREQ=MONTHLY",
+            "FREQ=WEEKLY;BYDAY=1MO",
+            "INTERVAL=2",
+            "FREQ=DAILY;INTERVAL=0",
+        ] {
+            assert!(rule.parse::<Recurrence>().is_err(), "{rule}");
+        }
+    }
+
+    #[tokio::test]
+    async fn series_gives_occurrence_of_the_day() {
+        let parsed = parse((2024, 10, 14)).await;
+
+        assert!(parsed.quarantined.is_empty(), "{:?}", parsed.quarantined);
+        let mut classes = parsed.classes;
+        classes.sort_by_key(|class| class.range.start);
+
+        let [lecture, workshop] = classes.as_slice() else {
+            panic!("{classes:?}");
+        };
+        assert_eq!(lecture.class_id, "series@pjatk/20241014T100000");
+        assert_eq!(
+            lecture.range.start.to_rfc3339(),
+            "2024-10-14T08:00:00+00:00"
+        );
+        assert_eq!(lecture.range.end.to_rfc3339(), "2024-10-14T09:30:00+00:00");
+        assert_eq!(lecture.groups.len(), 2);
+        assert_eq!(lecture.lecturers[0].last_name, "Kowalski");
+
+        assert_eq!(workshop.class_id, "workshop@pjatk");
+        assert_eq!(workshop.name, "Warsztaty, Rust");
+        assert_eq!(workshop.range.end.to_rfc3339(), "2024-10-14T13:30:00+00:00");
+        assert!(matches!(workshop.place, ClassPlace::Online));
+    }
+
+    #[tokio::test]
+    async fn excluded_and_overridden_occurrences_are_skipped() {
+        assert!(parse((2024, 10, 21)).await.classes.is_empty());
+
+        // moved two hours later, and past the change to winter time
+        let moved = parse((2024, 10, 28)).await.classes;
+        let [moved] = moved.as_slice() else {
+            panic!("{moved:?}");
+        };
+        assert_eq!(moved.class_id, "series@pjatk/20241028T100000");
+        assert_eq!(moved.range.start.to_rfc3339(), "2024-10-28T11:00:00+00:00");
+
+        assert_eq!(parse((2024, 11, 4)).await.classes.len(), 1);
+        assert!(parse((2024, 11, 11)).await.classes.is_empty());
+    }
+}
@@ -1,44 +1,316 @@
-use std::{collections::HashSet, convert::Infallible, hash::RandomState};
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    convert::Infallible,
+    hash::RandomState,
+};
 
-use bson::{doc, oid::ObjectId};
-use chrono::{NaiveDate, NaiveTime, TimeDelta, Utc};
+use bson::{doc, oid::ObjectId, serde_helpers::chrono_datetime_as_bson_datetime};
+use chrono::{DateTime, NaiveDate, NaiveTime, TimeDelta, TimeZone, Utc};
+use eyre::OptionExt;
 
 use futures::{Sink, SinkExt, StreamExt, TryStreamExt};
-use mongodb::Collection;
+use mongodb::{options::ReturnDocument, Collection};
 use serde::Serialize;
 use slog::Logger;
-use smallvec::SmallVec;
+use smallvec::{smallvec, SmallVec};
 
 use crate::{
     channels,
     db::{Model, OIDCollection, OID},
-    notifications::UpdateEvent,
+    notifications::{UpdateEvent, UpdateEvents},
+};
+
+use super::{
+    cache::ParseCache,
+    control::{Control, ControlReply, ControlRequest, ParserStatus},
+    types::{ChangeKind, Class, ClassChange, ClassField, QuarantinedClass, Reservation, TimeRange},
+    LayoutChanged, ParsedDay, ScheduleParser,
 };
 
-use super::{types::Class, ScheduleParser};
+#[derive(Debug)]
+pub struct ClassModification {
+    pub old: OID<Class>,
+    pub new: OID<Class>,
+    pub diff: Vec<ClassField>,
+}
 
 #[derive(Debug, Default)]
 pub struct ClassDelta {
     pub added_classes: Vec<OID<Class>>,
     pub removed_classes: Vec<OID<Class>>,
+    pub modified_classes: Vec<ClassModification>,
+    /// reservations which weren't stored for the day before
+    pub added_reservations: Vec<OID<Reservation>>,
+    /// days stored for the first time, nothing was known about their classes before
+    pub first_seen_days: Vec<NaiveDate>,
+    /// days which weren't stored, see [`MassRemovalError`]
+    pub held_removals: Vec<HeldRemoval>,
+}
+
+/// Day left as it was since too many of its classes would be removed
+#[derive(Debug, Clone)]
+pub struct HeldRemoval {
+    pub day: NaiveDate,
+    pub removed: usize,
+    /// wasn't held before, so admins haven't been told about it yet
+    pub newly_held: bool,
+}
+
+impl ClassDelta {
+    fn extend(&mut self, other: ClassDelta) {
+        self.added_classes.extend(other.added_classes);
+        self.removed_classes.extend(other.removed_classes);
+        self.modified_classes.extend(other.modified_classes);
+        self.added_reservations.extend(other.added_reservations);
+        self.held_removals.extend(other.held_removals);
+        self.first_seen_days.extend(other.first_seen_days);
+    }
+
+    // classes of a first seen day were there all along, they are news to nobody
+    fn is_first_seen(&self, range: &TimeRange) -> bool {
+        self.first_seen_days.contains(&local_day(range))
+    }
+
+    /// History records of the delta, see [`ClassChange`]
+    pub fn changes(&self) -> Vec<ClassChange> {
+        let added = self
+            .added_classes
+            .iter()
+            .map(|class| ClassChange::added(class.data.clone()));
+        let removed = self
+            .removed_classes
+            .iter()
+            .map(|class| ClassChange::removed(class.data.clone()));
+        let modified = self.modified_classes.iter().map(|modification| {
+            ClassChange::modified(
+                modification.old.data.clone(),
+                modification.new.data.clone(),
+                modification.diff.clone(),
+            )
+        });
+
+        added.chain(removed).chain(modified).collect()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.added_classes.is_empty()
+            && self.removed_classes.is_empty()
+            && self.modified_classes.is_empty()
+            && self.added_reservations.is_empty()
+    }
+
+    /// Events notification manager learns about the delta from,
+    /// `None` if nothing has changed
+    pub fn into_events(mut self) -> Option<UpdateEvents> {
+        // same as classes, reservations of a first seen day aren't news
+        let first_seen_days = &self.first_seen_days;
+        self.added_reservations
+            .retain(|reservation| !first_seen_days.contains(&local_day(&reservation.data.range)));
+
+        if self.is_empty() {
+            return None;
+        }
+
+        let mut events = SmallVec::new();
+
+        for added_class in &self.added_classes {
+            events.push(UpdateEvent::ClassAdded {
+                announce: !self.is_first_seen(&added_class.data.range),
+                class: added_class.clone(),
+            });
+        }
+        for removed_class in self.removed_classes {
+            events.push(UpdateEvent::ClassRemoved {
+                class: removed_class,
+            });
+        }
+        for modification in self.modified_classes {
+            events.push(UpdateEvent::ClassModified {
+                old: Box::new(modification.old),
+                new: modification.new,
+                diff: modification.diff,
+            });
+        }
+        for reservation in self.added_reservations {
+            events.push(UpdateEvent::ReservationAdded { reservation });
+        }
+
+        Some(events)
+    }
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("refusing to remove {removed} classes of {day} at once")]
+pub struct MassRemovalError {
+    pub day: NaiveDate,
+    pub removed: usize,
+}
+
+/// How much of a stored day one parse may remove before it's held back,
+/// an empty parse of a small day is caught by the share, of a big one by the count
+#[derive(Debug, Clone, Copy)]
+pub struct RemovalLimit {
+    pub max_classes: usize,
+    /// share of the day's stored classes, a day losing all of them is always held
+    pub max_share: f64,
+}
+
+impl RemovalLimit {
+    fn is_exceeded(&self, removed: usize, stored: usize) -> bool {
+        removed > 0
+            && (removed >= stored
+                || removed > self.max_classes
+                || removed as f64 > stored as f64 * self.max_share)
+    }
+}
+
+fn default_mass_removal_threshold() -> usize {
+    20
+}
+
+fn default_mass_removal_share() -> f64 {
+    0.5
+}
+
+fn default_failure_alert_threshold() -> u32 {
+    5
+}
+
+fn default_bootstrap() -> bool {
+    true
+}
+
+fn default_refresh_tiers() -> Vec<RefreshTier> {
+    let minutes = |minutes: u64| std::time::Duration::from_secs(minutes * 60);
+
+    vec![
+        RefreshTier {
+            from_day: 0,
+            every: minutes(10),
+        },
+        RefreshTier {
+            from_day: 2,
+            every: minutes(60),
+        },
+        RefreshTier {
+            from_day: 7,
+            every: minutes(24 * 60),
+        },
+    ]
+}
+
+fn default_cache_ttl() -> std::time::Duration {
+    std::time::Duration::from_secs(6 * 60 * 60)
+}
+
+/// Context attached to parse errors, counts failures since the last success
+#[derive(Debug)]
+pub struct ConsecutiveFailures(pub u32);
+
+impl std::fmt::Display for ConsecutiveFailures {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "parser failed {} times in a row", self.0)
+    }
+}
+
+/// Days at least `from_day` days away from today are parsed again
+/// once per `every`, until a tier further away takes over
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct RefreshTier {
+    pub from_day: u32,
+    pub every: std::time::Duration,
 }
 
 #[derive(serde::Deserialize, Debug)]
 pub struct Config {
     pub interval: std::time::Duration,
     pub days_ahead: u32,
+
+    /// How often stored days are refreshed depending on how near they are,
+    /// days closer than any tier follow the nearest one
+    #[serde(default = "default_refresh_tiers")]
+    pub refresh_tiers: Vec<RefreshTier>,
+
+    /// Removing more classes than this from one day is held back
+    /// until admins confirm it, see [`Control::Reparse`]
+    #[serde(default = "default_mass_removal_threshold")]
+    pub mass_removal_threshold: usize,
+
+    /// Same for removing more than this share of a day's classes,
+    /// or all of them
+    #[serde(default = "default_mass_removal_share")]
+    pub mass_removal_share: f64,
+
+    /// Admins are alerted once this many parses in a row have failed
+    #[serde(default = "default_failure_alert_threshold")]
+    pub failure_alert_threshold: u32,
+
+    /// How long fetched details and day fingerprints are trusted
+    /// before the source is asked again
+    #[serde(default = "default_cache_ttl")]
+    pub cache_ttl: std::time::Duration,
+
+    /// On first start the whole horizon is parsed at once,
+    /// instead of a day per `interval`
+    #[serde(default = "default_bootstrap")]
+    pub bootstrap: bool,
+
+    /// Tells apart several parsers of the same kind, defaults to the parser name
+    #[serde(default)]
+    pub name: Option<String>,
+
+    /// Tag of the stored classes, defaults to the one of the parser,
+    /// or to `name` for parsers which are a source of their own
+    #[serde(default)]
+    pub source: Option<String>,
 }
 #[derive(serde::Deserialize, Serialize, Default, Clone)]
 pub struct Data {
     pub name: String,
     pub last_day_reparsed: Option<NaiveDate>,
     pub last_day_parsed: Option<NaiveDate>,
+
+    /// days whose mass removal was held back and waits for confirmation
+    #[serde(default)]
+    pub held_removals: Vec<NaiveDate>,
+
+    #[serde(default)]
+    pub consecutive_failures: u32,
 }
 
 impl Model for Data {
     const COLLECTION_NAME: &'static str = "parsing_datas";
 }
 
+/// When the day was last parsed, decides when it's due again
+#[derive(serde::Deserialize, Serialize, Debug, Clone)]
+pub struct DayRefresh {
+    pub parser: String,
+    pub day: NaiveDate,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub refreshed_at: DateTime<Utc>,
+}
+
+impl Model for DayRefresh {
+    const COLLECTION_NAME: &'static str = "day_refreshes";
+}
+
+/// Snapshot of the page which the parser couldn't understand
+#[derive(serde::Deserialize, Serialize, Debug, Clone)]
+pub struct ParserFailure {
+    pub parser: String,
+    pub day: NaiveDate,
+    pub selector: String,
+    pub html: String,
+    pub backtrace: String,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub occurred_at: DateTime<Utc>,
+}
+
+impl Model for ParserFailure {
+    const COLLECTION_NAME: &'static str = "parser_failures";
+}
+
 // each variant date denotes day which is parsed this time
 enum SelectorKind {
     ParsingNew,
@@ -54,6 +326,19 @@ pub struct ParserManager<Parser: ScheduleParser> {
     parser: Parser,
     class_collection: Collection<Class>,
     data_collection: Collection<Data>,
+    quarantine_collection: Collection<QuarantinedClass>,
+    reservation_collection: Collection<Reservation>,
+    failure_collection: Collection<ParserFailure>,
+    refresh_collection: Collection<DayRefresh>,
+    change_collection: Collection<ClassChange>,
+    cache: ParseCache,
+    /// used when admins ask for a reparse and for days of the nearest
+    /// refresh tier, nothing is trusted then
+    bypass_cache: ParseCache,
+    name: &'static str,
+    source: &'static str,
+    /// days are diffed against the database but nothing is written
+    dry_run: bool,
     config: &'static Config,
     logger: Logger,
 }
@@ -67,27 +352,60 @@ impl<Parser: ScheduleParser> ParserManager<Parser> {
     ) -> Self {
         let class_collection = db.collection(Class::COLLECTION_NAME);
         let data_collection = db.collection(Data::COLLECTION_NAME);
-        let logger =
-            logger.new(slog::o! {"subsystem" => "parser.manager", "parser" => Parser::NAME});
+        let quarantine_collection = db.collection(QuarantinedClass::COLLECTION_NAME);
+        let reservation_collection = db.collection(Reservation::COLLECTION_NAME);
+        let failure_collection = db.collection(ParserFailure::COLLECTION_NAME);
+        let refresh_collection = db.collection(DayRefresh::COLLECTION_NAME);
+        let change_collection = db.collection(ClassChange::COLLECTION_NAME);
+        let name = config.name.as_deref().unwrap_or(Parser::NAME);
+        // e.g. two calendars must not cancel each other's classes
+        let default_source = match Parser::SOURCE == Parser::NAME {
+            true => name,
+            false => Parser::SOURCE,
+        };
+        let source = config.source.as_deref().unwrap_or(default_source);
+        let cache = ParseCache::new(db, name, config.cache_ttl);
+        let bypass_cache = ParseCache::new(db, name, std::time::Duration::ZERO);
+        let logger = logger.new(slog::o! {"subsystem" => "parser.manager", "parser" => name});
 
         Self {
             class_collection,
             data_collection,
+            quarantine_collection,
+            reservation_collection,
+            failure_collection,
+            refresh_collection,
+            change_collection,
+            cache,
+            bypass_cache,
             parser,
+            name,
+            source,
+            dry_run: false,
             logger,
             config,
         }
     }
 
+    /// Only reports what parsing would change, see [`ParserManager::backfill`]
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        if dry_run {
+            self.cache = ParseCache::disabled(self.name);
+            self.bypass_cache = ParseCache::disabled(self.name);
+        }
+        self
+    }
+
     async fn get_maximum_day_parsed(&self, data: &Data) -> eyre::Result<Option<NaiveDate>> {
         if let Some(date) = data.last_day_parsed {
             return Ok(Some(date.clone()));
         }
 
-        // query to get the latest class
+        // query to get the latest class of our source
         let max_class = self
             .class_collection
-            .find_one(bson::doc! {})
+            .find_one(bson::doc! {"source": self.source})
             .sort(bson::doc! {"range.start": -1})
             .await?;
 
@@ -97,16 +415,15 @@ impl<Parser: ScheduleParser> ParserManager<Parser> {
     async fn get_current_parser_data(&self) -> eyre::Result<Data> {
         let data_query = self
             .data_collection
-            .find_one(doc! {"name": Parser::NAME})
+            .find_one(doc! {"name": self.name})
             .await?;
 
         match data_query {
             Some(data) => return Ok(data),
             None => {
                 let new_data = Data {
-                    name: Parser::NAME.to_owned(),
-                    last_day_reparsed: None,
-                    last_day_parsed: None,
+                    name: self.name.to_owned(),
+                    ..Default::default()
                 };
                 self.data_collection.insert_one(new_data.clone()).await?;
 
@@ -115,7 +432,60 @@ impl<Parser: ScheduleParser> ParserManager<Parser> {
         }
     }
 
-    async fn select_date(&self, data: &Data) -> eyre::Result<DaySelector> {
+    async fn quarantine(&self, quarantined: Vec<QuarantinedClass>) -> eyre::Result<()> {
+        for mut class in quarantined {
+            class.parser = self.name.to_owned();
+            slog::warn!(self.logger, "parser.quarantined"; "source_id" => &class.source_id, "err" => &class.error);
+
+            // same record fails on every reparse, so keep only the latest attempt
+            self.quarantine_collection
+                .find_one_and_replace(
+                    doc! {"parser": &class.parser, "source_id": &class.source_id},
+                    class,
+                )
+                .upsert(true)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    // returns number of failures in a row, including this one
+    async fn record_failure(&self, day: NaiveDate, err: &eyre::Report) -> eyre::Result<u32> {
+        if let Some(layout) = err.downcast_ref::<LayoutChanged>() {
+            let failure = ParserFailure {
+                parser: self.name.to_owned(),
+                day,
+                selector: layout.selector.clone(),
+                html: layout.html.clone(),
+                backtrace: layout.backtrace.clone(),
+                occurred_at: Utc::now(),
+            };
+            // a broken parser fails the same way on every parse, so keep only the latest page
+            self.failure_collection
+                .find_one_and_replace(
+                    doc! {"parser": &failure.parser, "selector": &failure.selector},
+                    failure,
+                )
+                .upsert(true)
+                .await?;
+        }
+
+        let data = self
+            .data_collection
+            .find_one_and_update(
+                doc! {"name": self.name},
+                doc! {"$inc": {"consecutive_failures": 1}},
+            )
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .await?;
+
+        Ok(data.map(|data| data.consecutive_failures).unwrap_or(1))
+    }
+
+    // `None` when every day of the horizon is fresh enough
+    async fn select_date(&self, data: &Data) -> eyre::Result<Option<DaySelector>> {
         let maximum_date_parsed = self.get_maximum_day_parsed(&data).await?;
 
         let today = Utc::now().date_naive();
@@ -125,161 +495,726 @@ impl<Parser: ScheduleParser> ParserManager<Parser> {
                 let delta = date_max - today;
                 let day = today + (delta + TimeDelta::days(1));
                 slog::info!(&self.logger, "selecting date"; "mode" => "ParsingNew", "date" => day.to_string());
-                return Ok(DaySelector {
+                return Ok(Some(DaySelector {
                     date: day,
                     kind: SelectorKind::ParsingNew,
-                });
+                }));
             }
             None => {
                 slog::info!(&self.logger, "selecting date"; "mode" => "ParsingNew", "date" => today.to_string());
 
-                return Ok(DaySelector {
+                return Ok(Some(DaySelector {
                     date: today,
                     kind: SelectorKind::ParsingNew,
-                });
+                }));
             }
             _ => (),
         }
 
-        let next_date_reparse = data
-            .last_day_reparsed
-            .filter(|last_reparsed| {
-                last_reparsed
-                    < &(Utc::now().date_naive() + TimeDelta::days(self.config.days_ahead as i64))
-            })
-            .map(|last_reparsed| last_reparsed + TimeDelta::days(1))
-            .unwrap_or(today);
+        let Some(date) = self.most_overdue_day(today).await? else {
+            slog::debug!(&self.logger, "selecting date"; "mode" => "idle");
+            return Ok(None);
+        };
 
-        slog::info!(&self.logger, "selecting date"; "mode" => "reparsing", "date" => next_date_reparse.to_string());
+        slog::info!(&self.logger, "selecting date"; "mode" => "reparsing", "date" => date.to_string());
 
-        Ok(DaySelector {
-            date: next_date_reparse,
+        Ok(Some(DaySelector {
+            date,
             kind: SelectorKind::Refreshing,
+        }))
+    }
+
+    fn refresh_tier(&self, days_away: u32) -> Option<&RefreshTier> {
+        let tiers = &self.config.refresh_tiers;
+        tiers
+            .iter()
+            .filter(|tier| tier.from_day <= days_away)
+            .max_by_key(|tier| tier.from_day)
+            .or_else(|| tiers.iter().min_by_key(|tier| tier.from_day))
+    }
+
+    fn refresh_interval(&self, days_away: u32) -> TimeDelta {
+        // without tiers days are simply refreshed one after another
+        self.refresh_tier(days_away)
+            .map(|tier| TimeDelta::from_std(tier.every).unwrap_or(TimeDelta::max_value()))
+            .unwrap_or_default()
+    }
+
+    // overview doesn't show every change of a class, e.g. of its room,
+    // so days refreshed most often are fetched in full every time
+    fn is_in_nearest_tier(&self, day: NaiveDate) -> bool {
+        let days_away = (day - Utc::now().date_naive()).num_days().max(0) as u32;
+        let nearest = self
+            .config
+            .refresh_tiers
+            .iter()
+            .min_by_key(|tier| tier.from_day);
+
+        match (self.refresh_tier(days_away), nearest) {
+            (Some(tier), Some(nearest)) => tier.from_day == nearest.from_day,
+            _ => false,
+        }
+    }
+
+    // day of the horizon which waits the longest past its refresh time
+    async fn most_overdue_day(&self, today: NaiveDate) -> eyre::Result<Option<NaiveDate>> {
+        let refreshed: HashMap<_, _> = self
+            .refresh_collection
+            .find(doc! {"parser": self.name, "day": {"$gte": today.to_string()}})
+            .await?
+            .map_ok(|refresh| (refresh.day, refresh.refreshed_at))
+            .try_collect()
+            .await?;
+
+        let now = Utc::now();
+        let mut most_overdue: Option<(DateTime<Utc>, NaiveDate)> = None;
+
+        for days_away in 0..=self.config.days_ahead {
+            let day = today + TimeDelta::days(days_away as i64);
+            let due_at = match refreshed.get(&day) {
+                Some(refreshed_at) => refreshed_at
+                    .checked_add_signed(self.refresh_interval(days_away))
+                    .unwrap_or(DateTime::<Utc>::MAX_UTC),
+                None => DateTime::<Utc>::MIN_UTC,
+            };
+
+            // on ties the nearer day wins
+            if due_at <= now && most_overdue.is_none_or(|(earliest, _)| due_at < earliest) {
+                most_overdue = Some((due_at, day));
+            }
+        }
+
+        Ok(most_overdue.map(|(_, day)| day))
+    }
+
+    // a day is recorded once stored, so without a record it was never stored,
+    // e.g. a new day at the end of the horizon or any day of the first start
+    async fn is_first_seen(&self, day: NaiveDate) -> eyre::Result<bool> {
+        let refresh = self
+            .refresh_collection
+            .find_one(doc! {"parser": self.name, "day": day.to_string()})
+            .await?;
+
+        Ok(refresh.is_none())
+    }
+
+    async fn record_refresh(&self, day: NaiveDate) -> eyre::Result<()> {
+        let refresh = DayRefresh {
+            parser: self.name.to_owned(),
+            day,
+            refreshed_at: Utc::now(),
+        };
+
+        self.refresh_collection
+            .find_one_and_replace(doc! {"parser": self.name, "day": day.to_string()}, refresh)
+            .upsert(true)
+            .await?;
+
+        // past days are never refreshed again
+        let today = Utc::now().date_naive();
+        self.refresh_collection
+            .delete_many(doc! {"parser": self.name, "day": {"$lt": today.to_string()}})
+            .await?;
+
+        Ok(())
+    }
+
+    // diffs parsed day against the stored one and saves it
+    async fn store_day(
+        &self,
+        day: NaiveDate,
+        parsed_day: ParsedDay,
+        removal_limit: Option<RemovalLimit>,
+    ) -> eyre::Result<ClassDelta> {
+        if parsed_day.unchanged {
+            slog::info!(self.logger, "parser.day_unchanged"; "date" => day.to_string());
+            return Ok(ClassDelta::default());
+        }
+
+        let first_seen = self.is_first_seen(day).await?;
+
+        let quarantined_ids: HashSet<_> = parsed_day
+            .quarantined
+            .iter()
+            .map(|class| class.source_id.clone())
+            .collect();
+        if !self.dry_run {
+            self.quarantine(parsed_day.quarantined).await?;
+        }
+
+        let classes = parsed_day.classes.into_iter().map(|class| Class {
+            source: self.source.to_owned(),
+            ..class
+        });
+
+        let mut class_delta = replace_or_fill_day(
+            &self.class_collection,
+            day,
+            self.source,
+            classes,
+            |class| {
+                class.source == self.source
+                    && !quarantined_ids.contains(&class.class_id)
+                    && self.parser.covers(class)
+            },
+            removal_limit,
+            self.dry_run,
+        )
+        .await?;
+        if first_seen {
+            class_delta.first_seen_days.push(day);
+        }
+
+        // reservations have no diff of their own, so they are left out of dry runs
+        if self.dry_run {
+            return Ok(class_delta);
+        }
+
+        record_changes(&self.change_collection, &class_delta).await?;
+
+        if let Some(reservations) = parsed_day.reservations {
+            class_delta.added_reservations =
+                replace_day_reservations(&self.reservation_collection, day, reservations).await?;
+        }
+
+        // only now the day is really stored, so it can be skipped next time
+        if let Some(fingerprint) = parsed_day.fingerprint {
+            self.cache.store_day(day, fingerprint).await?;
+        }
+
+        Ok(class_delta)
+    }
+
+    // mass removal only skips the day instead of failing the whole parse,
+    // unless `allow_mass_removal`
+    async fn store_or_hold(
+        &self,
+        day: NaiveDate,
+        parsed_day: ParsedDay,
+        allow_mass_removal: bool,
+    ) -> eyre::Result<ClassDelta> {
+        let removal_limit = (!allow_mass_removal).then_some(RemovalLimit {
+            max_classes: self.config.mass_removal_threshold,
+            max_share: self.config.mass_removal_share,
+        });
+
+        let err = match self.store_day(day, parsed_day, removal_limit).await {
+            Ok(delta) => {
+                if !self.dry_run {
+                    self.data_collection
+                        .update_one(
+                            doc! {"name": self.name},
+                            doc! {"$pull": {"held_removals": day.to_string()}},
+                        )
+                        .await?;
+                }
+                return Ok(delta);
+            }
+            Err(err) => err,
+        };
+
+        let Some(mass_removal) = err.downcast_ref::<MassRemovalError>() else {
+            return Err(err);
+        };
+        slog::warn!(self.logger, "parser.mass_removal_held"; "day" => day.to_string(), "removed" => mass_removal.removed);
+
+        let newly_held = !self.dry_run && self.hold_removal(day).await?;
+
+        Ok(ClassDelta {
+            held_removals: vec![HeldRemoval {
+                day,
+                removed: mass_removal.removed,
+                newly_held,
+            }],
+            ..Default::default()
         })
     }
 
+    // returns whether the day wasn't held already
+    async fn hold_removal(&self, day: NaiveDate) -> eyre::Result<bool> {
+        let data = self.get_current_parser_data().await?;
+        if data.held_removals.contains(&day) {
+            return Ok(false);
+        }
+
+        self.data_collection
+            .update_one(
+                doc! {"name": self.name},
+                doc! {"$addToSet": {"held_removals": day.to_string()}},
+            )
+            .await?;
+
+        Ok(true)
+    }
+
+    /// Fills the whole horizon at once if nothing was parsed before,
+    /// returns `None` when there was no need to
+    pub async fn bootstrap(&mut self) -> eyre::Result<Option<ClassDelta>> {
+        let current_data = self.get_current_parser_data().await?;
+        if self.get_maximum_day_parsed(&current_data).await?.is_some() {
+            return Ok(None);
+        }
+
+        let today = Utc::now().date_naive();
+        let last_day = today + TimeDelta::days(self.config.days_ahead as i64);
+        slog::info!(self.logger, "parser.bootstrap"; "from" => today.to_string(), "to" => last_day.to_string());
+
+        let days = match self.parser.parse_range(today, last_day, &self.cache).await {
+            Ok(days) => days,
+            Err(err) => {
+                let failures = self.record_failure(today, &err).await?;
+                return Err(err.wrap_err(ConsecutiveFailures(failures)));
+            }
+        };
+
+        let mut class_delta = ClassDelta::default();
+        for (day, parsed_day) in days {
+            class_delta.extend(self.store_or_hold(day, parsed_day, false).await?);
+            self.record_refresh(day).await?;
+        }
+
+        // only the fields of progress, held days were written meanwhile
+        self.data_collection
+            .update_one(
+                doc! {"name": self.name},
+                doc! {"$set": {"last_day_parsed": last_day.to_string(), "consecutive_failures": 0}},
+            )
+            .upsert(true)
+            .await?;
+
+        Ok(Some(class_delta))
+    }
+
     pub async fn parse_next(&mut self) -> eyre::Result<ClassDelta> {
         let current_data = self.get_current_parser_data().await?;
 
-        let selector = self.select_date(&current_data).await?;
-        let parsed_day = self.parser.parse_day(selector.date.clone()).await?;
-        let class_delta =
-            replace_or_fill_day(&self.class_collection, parsed_day.into_iter()).await?;
+        let Some(selector) = self.select_date(&current_data).await? else {
+            return Ok(ClassDelta::default());
+        };
+        let cache = match self.is_in_nearest_tier(selector.date) {
+            true => &self.bypass_cache,
+            false => &self.cache,
+        };
+        let parsed_day = match self.parser.parse_day(selector.date, cache).await {
+            Ok(parsed_day) => parsed_day,
+            Err(err) => {
+                let failures = self.record_failure(selector.date, &err).await?;
+                return Err(err.wrap_err(ConsecutiveFailures(failures)));
+            }
+        };
+
+        // a held day is skipped like any other, it's parsed again once due
+        let class_delta = self.store_or_hold(selector.date, parsed_day, false).await?;
 
-        let data_update = match selector.kind {
-            SelectorKind::ParsingNew => Data {
-                last_day_parsed: Some(selector.date),
-                ..current_data
-            },
-            SelectorKind::Refreshing => Data {
-                last_day_reparsed: Some(selector.date),
-                ..current_data
-            },
+        let progress_field = match selector.kind {
+            SelectorKind::ParsingNew => "last_day_parsed",
+            SelectorKind::Refreshing => "last_day_reparsed",
         };
 
+        // only the fields of progress, held days were written meanwhile
         self.data_collection
-            .find_one_and_replace(doc! {"name": Parser::NAME}, data_update)
+            .update_one(
+                doc! {"name": self.name},
+                doc! {"$set": {progress_field: selector.date.to_string(), "consecutive_failures": 0}},
+            )
             .upsert(true)
             .await?;
+        self.record_refresh(selector.date).await?;
+
         Ok(class_delta)
     }
 
+    fn delta_events(&self, delta: ClassDelta) -> Option<UpdateEvents> {
+        slog::info!(self.logger, "parser.got_delta"; "added" => delta.added_classes.len(), "removed" => delta.removed_classes.len(), "modified" => delta.modified_classes.len(), "reservations" => delta.added_reservations.len(), "held" => delta.held_removals.len());
+
+        // admins are told about a held day once, not on every parse of it
+        let alerts: UpdateEvents = delta
+            .held_removals
+            .iter()
+            .filter(|held| held.newly_held)
+            .map(|held| UpdateEvent::MassRemovalHeld {
+                parser: self.name,
+                day: held.day,
+                removed: held.removed,
+            })
+            .collect();
+
+        let mut events = delta.into_events().unwrap_or_default();
+        events.extend(alerts);
+
+        (!events.is_empty()).then_some(events)
+    }
+
+    // admins are alerted once failures cross the threshold
+    fn error_events(&self, err: eyre::Report) -> Option<UpdateEvents> {
+        slog::error!(self.logger, "parser.errored"; "err" => ?err);
+
+        // alert only when crossing the threshold, not on every failure after it
+        let failures = err
+            .downcast_ref::<ConsecutiveFailures>()
+            .filter(|failures| failures.0 == self.config.failure_alert_threshold)?;
+
+        Some(smallvec![UpdateEvent::ParserFailing {
+            parser: self.name,
+            failures: failures.0,
+            error: err.root_cause().to_string(),
+        }])
+    }
+
+    // parses days out of turn, e.g. after students reported a change
+    async fn reparse(
+        &mut self,
+        start: NaiveDate,
+        end: NaiveDate,
+        allow_mass_removal: bool,
+    ) -> eyre::Result<(usize, ClassDelta)> {
+        let days = (end - start).num_days() + 1;
+        if days < 1 || days > self.config.days_ahead as i64 + 1 {
+            eyre::bail!(
+                "range should cover from 1 to {} days",
+                self.config.days_ahead + 1
+            );
+        }
+
+        slog::info!(self.logger, "parser.reparse"; "from" => start.to_string(), "to" => end.to_string(), "allow_mass_removal" => allow_mass_removal);
+        self.parse_range(start, end, allow_mass_removal).await
+    }
+
+    /// Parses and stores days from `start` to `end` inclusive, past ones too,
+    /// regardless of the horizon and without moving parsing progress.
+    /// Days with mass removal are held back, unless `allow_mass_removal`.
+    /// Returns number of days parsed and what has changed
+    pub async fn backfill(
+        &mut self,
+        start: NaiveDate,
+        end: NaiveDate,
+        allow_mass_removal: bool,
+    ) -> eyre::Result<(usize, ClassDelta)> {
+        if end < start {
+            eyre::bail!("range ends before it starts");
+        }
+
+        slog::info!(self.logger, "parser.backfill"; "from" => start.to_string(), "to" => end.to_string(), "dry_run" => self.dry_run, "allow_mass_removal" => allow_mass_removal);
+        self.parse_range(start, end, allow_mass_removal).await
+    }
+
+    // cache is bypassed, whoever asks for a range wants it parsed for real
+    async fn parse_range(
+        &mut self,
+        start: NaiveDate,
+        end: NaiveDate,
+        allow_mass_removal: bool,
+    ) -> eyre::Result<(usize, ClassDelta)> {
+        let days = self
+            .parser
+            .parse_range(start, end, &self.bypass_cache)
+            .await?;
+
+        let parsed = days.len();
+        let mut class_delta = ClassDelta::default();
+        for (day, parsed_day) in days {
+            class_delta.extend(
+                self.store_or_hold(day, parsed_day, allow_mass_removal)
+                    .await?,
+            );
+            if !self.dry_run {
+                self.record_refresh(day).await?;
+            }
+        }
+
+        Ok((parsed, class_delta))
+    }
+
+    async fn handle_control(
+        &mut self,
+        control: Control,
+        paused: &mut bool,
+    ) -> (ControlReply, Option<UpdateEvents>) {
+        slog::info!(self.logger, "parser.control"; "control" => ?control);
+
+        match control {
+            Control::Reparse {
+                start,
+                end,
+                allow_mass_removal,
+            } => match self.reparse(start, end, allow_mass_removal).await {
+                Ok((days, delta)) => {
+                    let reply = ControlReply::Reparsed {
+                        days,
+                        added: delta.added_classes.len(),
+                        removed: delta.removed_classes.len(),
+                        modified: delta.modified_classes.len(),
+                        held: delta.held_removals.iter().map(|held| held.day).collect(),
+                    };
+
+                    (reply, self.delta_events(delta))
+                }
+                Err(err) => {
+                    slog::error!(self.logger, "parser.reparse_failed"; "err" => ?err);
+                    (ControlReply::Failed(format!("{err:#}")), None)
+                }
+            },
+            Control::Pause => {
+                *paused = true;
+                (ControlReply::Paused, None)
+            }
+            Control::Resume => {
+                *paused = false;
+                (ControlReply::Resumed, None)
+            }
+            Control::Status => match self.get_current_parser_data().await {
+                Ok(data) => {
+                    let status = ParserStatus {
+                        paused: *paused,
+                        last_day_parsed: data.last_day_parsed,
+                        consecutive_failures: data.consecutive_failures,
+                        held_removals: data.held_removals,
+                    };
+
+                    (ControlReply::Status(status), None)
+                }
+                Err(err) => (ControlReply::Failed(format!("{err:#}")), None),
+            },
+        }
+    }
+
     pub fn work(
         mut self,
         events_consumer: impl channels::Tx<crate::notifications::UpdateEvents>,
+        control: impl channels::Rx<ControlRequest>,
     ) -> tokio::task::JoinHandle<eyre::Result<Infallible>> {
         let fut = async move {
+            // on failure horizon is simply filled day by day
+            if self.config.bootstrap {
+                let events = match self.bootstrap().await {
+                    Ok(delta) => delta.and_then(|delta| self.delta_events(delta)),
+                    Err(err) => self.error_events(err),
+                };
+
+                if let Some(events) = events {
+                    if events_consumer.send(events).await.is_err() {
+                        slog::error!(self.logger, "parser.delta_channel_err");
+                    }
+                }
+            }
+
+            let mut paused = false;
             loop {
-                let result = self.parse_next().await;
+                if !paused {
+                    let events = match self.parse_next().await {
+                        Ok(delta) => self.delta_events(delta),
+                        Err(err) => self.error_events(err),
+                    };
 
-                match result {
-                    Ok(delta) => {
-                        slog::info!(self.logger, "parser.got_delta"; "added" => delta.added_classes.len(), "removed" => delta.removed_classes.len());
+                    if let Some(events) = events {
+                        if events_consumer.send(events).await.is_err() {
+                            slog::error!(self.logger, "parser.delta_channel_err");
+                        }
+                    }
+                }
 
-                        let mut events = SmallVec::new();
+                // controls are handled while waiting, without delaying the next parse
+                let next_parse = tokio::time::sleep(self.config.interval);
+                tokio::pin!(next_parse);
 
-                        // lol, de'morgan law in action
-                        let should_send =
-                            !delta.added_classes.is_empty() || !delta.removed_classes.is_empty();
+                loop {
+                    let request = tokio::select! {
+                        _ = &mut next_parse => break,
+                        request = control.recv() => request,
+                    };
 
-                        if !should_send {
-                            continue;
-                        }
+                    let Ok(request) = request else {
+                        // nobody controls the parser anymore
+                        (&mut next_parse).await;
+                        break;
+                    };
 
-                        for added_class in delta.added_classes {
-                            events.push(UpdateEvent::ClassAdded { class: added_class });
-                        }
-                        for removed_class in delta.removed_classes {
-                            events.push(UpdateEvent::ClassRemoved {
-                                class: removed_class,
-                            });
-                        }
+                    let (reply, events) = self.handle_control(request.control, &mut paused).await;
+                    // asker may have given up waiting
+                    let _ = request.reply.send(reply);
 
+                    if let Some(events) = events {
                         if events_consumer.send(events).await.is_err() {
                             slog::error!(self.logger, "parser.delta_channel_err");
                         }
                     }
+                }
+            }
+        };
+        tokio::task::spawn(fut)
+    }
+}
 
-                    Err(err) => {
-                        slog::error!(self.logger, "parser.errored"; "err" => ?err);
-                    }
+fn local_day(range: &TimeRange) -> NaiveDate {
+    range.start.with_timezone(&crate::BOT_TIMEZONE).date_naive()
+}
+
+// days are the ones PJATK shows, so they are local to the university
+pub fn day_start(day: NaiveDate) -> eyre::Result<DateTime<chrono_tz::Tz>> {
+    crate::BOT_TIMEZONE
+        .from_local_datetime(&day.and_time(NaiveTime::MIN))
+        .earliest()
+        .ok_or_eyre("day start doesn't exist in bot timezone")
+}
+
+/// What storing a day changes, see [`replace_or_fill_day`]
+struct DayChanges {
+    delta: ClassDelta,
+    /// copies of a class stored more than once
+    duplicates: Vec<OID<Class>>,
+    /// differ only in fields users aren't told about, e.g. filled by a newer parser
+    silent_updates: Vec<OID<Class>>,
+}
+
+fn diff_day(
+    day: NaiveDate,
+    classes_in_db: Vec<OID<Class>>,
+    classes_new: Vec<Class>,
+    removable: impl Fn(&Class) -> bool,
+    removal_limit: Option<RemovalLimit>,
+) -> eyre::Result<DayChanges> {
+    let mut delta = ClassDelta::default();
+    let mut duplicates = Vec::new();
+    let mut silent_updates = Vec::new();
+
+    // what the day had, classes picked up from other days don't count
+    let stored = classes_in_db
+        .iter()
+        .filter(|class_in_db| {
+            local_day(&class_in_db.data.range) == day && removable(&class_in_db.data)
+        })
+        .count();
 
-                    _ => (),
+    let mut by_key = HashMap::new();
+    for class_in_db in classes_in_db {
+        let key = (
+            class_in_db.data.source.clone(),
+            class_in_db.data.class_id.clone(),
+        );
+        match by_key.entry(key) {
+            Entry::Occupied(_) => duplicates.push(class_in_db),
+            Entry::Vacant(vacant) => {
+                vacant.insert(class_in_db);
+            }
+        }
+    }
+
+    for class_new in classes_new {
+        let key = (class_new.source.clone(), class_new.class_id.clone());
+        match by_key.remove(&key) {
+            None => delta.added_classes.push(OID {
+                id: ObjectId::new(),
+                data: class_new,
+            }),
+            Some(class_in_db) if class_in_db.data == class_new => (),
+            Some(class_in_db) => {
+                let diff = class_in_db.data.diff(&class_new);
+                if diff.is_empty() {
+                    silent_updates.push(OID {
+                        id: class_in_db.id,
+                        data: class_new,
+                    });
+                    continue;
                 }
 
-                tokio::time::sleep(self.config.interval).await;
+                delta.modified_classes.push(ClassModification {
+                    new: OID {
+                        id: class_in_db.id,
+                        data: class_new,
+                    },
+                    old: class_in_db,
+                    diff,
+                });
             }
-        };
-        tokio::task::spawn(fut)
+        }
+    }
+
+    // leftovers weren't returned by the parser, so they were cancelled
+    delta.removed_classes = by_key
+        .into_values()
+        .filter(|class_in_db| removable(&class_in_db.data))
+        .collect();
+
+    let removed = delta.removed_classes.len();
+    if removal_limit.is_some_and(|limit| limit.is_exceeded(removed, stored)) {
+        eyre::bail!(MassRemovalError { day, removed });
     }
+
+    Ok(DayChanges {
+        delta,
+        duplicates,
+        silent_updates,
+    })
 }
 
 // In case db already contrains classes for this day,
-// will return classes that were deleted
+// will return classes that were deleted or changed
 // e.g. user might want notification if class was cancelled
+//
+// classes are matched by their source and source id, so a moved class is
+// reported as modified rather than as removal + addition, while sources
+// which happen to share ids don't take over each other's classes.
+// Only leftovers for which `removable` holds are treated as cancelled,
+// e.g. classes of other sources or the ones the parser failed
+// to read this time are kept as-is.
+// If removals exceed `removal_limit`, nothing is written
+// and `MassRemovalError` is returned.
+// With `dry_run` the delta is only computed, nothing is written either
 pub async fn replace_or_fill_day(
     coll: &Collection<Class>,
+    day: NaiveDate,
+    source: &str,
     classes: impl Iterator<Item = Class>,
+    removable: impl Fn(&Class) -> bool,
+    removal_limit: Option<RemovalLimit>,
+    dry_run: bool,
 ) -> eyre::Result<ClassDelta> {
-    let mut delta = ClassDelta::default();
-    let mut classes_new = classes.peekable();
+    let classes_new: Vec<_> = classes.collect();
 
-    let Some(first_new_class) = classes_new.peek() else {
-        return Ok(delta);
-    };
+    let day_start = day_start(day)?;
 
     let coll = coll.clone_with_type::<OID<Class>>();
-    let classes_in_db_query = crate::db::create_range_query(&first_new_class.range.start, None);
+    let new_ids: Vec<_> = classes_new
+        .iter()
+        .map(|class| class.class_id.as_str())
+        .collect();
 
-    let classes_in_db: Vec<_> = coll.find(classes_in_db_query).await?.try_collect().await?;
+    // classes moved from another day are picked up by id
+    let classes_in_db_query = doc! {"$or": [
+        crate::db::create_range_query(&day_start, None),
+        {"source": source, "class_id": {"$in": new_ids}},
+    ]};
+    let classes_in_db = coll.find(classes_in_db_query).await?.try_collect().await?;
 
-    // first we find classes that aren't present
-    for class_new in classes_new.by_ref() {
-        let does_db_have = classes_in_db
-            .iter()
-            .any(|db_class| db_class.data == class_new);
+    let DayChanges {
+        delta,
+        duplicates,
+        silent_updates,
+    } = diff_day(day, classes_in_db, classes_new, removable, removal_limit)?;
 
-        if !does_db_have {
-            let class_new = OID {
-                id: ObjectId::new(),
-                data: class_new.clone(),
-            };
-
-            delta.added_classes.push(class_new);
-        }
+    if dry_run {
+        return Ok(delta);
     }
 
     let mut session = coll.client().start_session().await?;
     session.start_transaction().await?;
 
-    // now we remove all classes from db that were cancelled
-    for class_in_db in classes_in_db {
-        let does_new_includes = classes_new.any(|new_class| &new_class == &class_in_db.data);
+    for class_in_db in delta.removed_classes.iter() {
+        coll.delete_one(doc! {"_id": &class_in_db.id}).await?;
+    }
 
-        if does_new_includes {
-            coll.delete_one(doc! {"_id": &class_in_db.id}).await?;
-            delta.removed_classes.push(class_in_db);
-        }
+    // copies of the same class, nobody should be notified about them
+    for duplicate in duplicates {
+        coll.delete_one(doc! {"_id": &duplicate.id}).await?;
+    }
+
+    for modification in delta.modified_classes.iter() {
+        coll.replace_one(doc! {"_id": &modification.new.id}, &modification.new)
+            .await?;
+    }
+
+    for class in silent_updates {
+        coll.replace_one(doc! {"_id": &class.id}, &class).await?;
     }
 
     // batch insert all classes that are new
@@ -293,3 +1228,209 @@ pub async fn replace_or_fill_day(
 
     Ok(delta)
 }
+
+/// Writes what happened to classes into their history,
+/// classes of first seen days weren't added by anybody, so they are left out
+pub async fn record_changes(
+    coll: &Collection<ClassChange>,
+    delta: &ClassDelta,
+) -> eyre::Result<()> {
+    let changes: Vec<_> = delta
+        .changes()
+        .into_iter()
+        .filter(|change| {
+            let first_seen = change
+                .after
+                .as_ref()
+                .is_some_and(|class| delta.is_first_seen(&class.range));
+            !(matches!(change.kind, ChangeKind::Added) && first_seen)
+        })
+        .collect();
+    if !changes.is_empty() {
+        coll.insert_many(changes).await?;
+    }
+
+    Ok(())
+}
+
+// Reservations aren't tracked for changes, the day is simply replaced.
+// Ones seen before keep their database id, newly seen ones are returned
+pub async fn replace_day_reservations(
+    coll: &Collection<Reservation>,
+    day: NaiveDate,
+    reservations: Vec<Reservation>,
+) -> eyre::Result<Vec<OID<Reservation>>> {
+    let coll = coll.clone_with_type::<OID<Reservation>>();
+    let day_query = crate::db::create_range_query(&day_start(day)?, None);
+
+    let stored: HashMap<_, _> = coll
+        .find(day_query.clone())
+        .await?
+        .map_ok(|stored| (stored.data.reservation_id.clone(), stored.id))
+        .try_collect()
+        .await?;
+
+    let mut added = Vec::new();
+    let reservations: Vec<_> = reservations
+        .into_iter()
+        .map(
+            |reservation| match stored.get(&reservation.reservation_id) {
+                Some(id) => OID {
+                    id: *id,
+                    data: reservation,
+                },
+                None => {
+                    let reservation = OID {
+                        id: ObjectId::new(),
+                        data: reservation,
+                    };
+                    added.push(reservation.clone());
+                    reservation
+                }
+            },
+        )
+        .collect();
+
+    coll.delete_many(day_query).await?;
+    if !reservations.is_empty() {
+        coll.insert_many(reservations).await?;
+    }
+
+    Ok(added)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parsing::types::{ClassKind, ClassPlace};
+
+    use super::*;
+
+    fn day() -> NaiveDate {
+        NaiveDate::from_ymd_opt(2024, 10, 14).unwrap()
+    }
+
+    fn stored(count: usize) -> Vec<OID<Class>> {
+        let start = day_start(day()).unwrap().with_timezone(&Utc) + TimeDelta::hours(8);
+
+        (0..count)
+            .map(|index| OID {
+                id: ObjectId::new(),
+                data: Class {
+                    class_id: index.to_string(),
+                    name: "Bazy danych".to_owned(),
+                    code: "BYT".to_owned(),
+                    kind: ClassKind::Lecture,
+                    lecturers: Vec::new(),
+                    range: TimeRange {
+                        start,
+                        end: start + TimeDelta::minutes(90),
+                    },
+                    place: ClassPlace::Online,
+                    groups: Vec::new(),
+                    study_mode: None,
+                    language: None,
+                    source: "pjatk".to_owned(),
+                },
+            })
+            .collect()
+    }
+
+    const LIMIT: RemovalLimit = RemovalLimit {
+        max_classes: 20,
+        max_share: 0.5,
+    };
+
+    // empty parse of the day, e.g. the source showed a blank page
+    fn remove_all(
+        classes_in_db: Vec<OID<Class>>,
+        limit: Option<RemovalLimit>,
+    ) -> eyre::Result<DayChanges> {
+        diff_day(day(), classes_in_db, Vec::new(), |_| true, limit)
+    }
+
+    #[test]
+    fn empty_parse_of_empty_day_changes_nothing() {
+        let changes = remove_all(Vec::new(), Some(LIMIT)).unwrap();
+
+        assert!(changes.delta.removed_classes.is_empty());
+        assert!(changes.delta.added_classes.is_empty());
+    }
+
+    #[test]
+    fn empty_parse_of_stored_day_is_held() {
+        // far under the absolute limit, still the whole day
+        for count in [1, 3, 30] {
+            let err = remove_all(stored(count), Some(LIMIT)).err().unwrap();
+            let err = err.downcast_ref::<MassRemovalError>().unwrap();
+
+            assert_eq!((err.day, err.removed), (day(), count));
+        }
+    }
+
+    #[test]
+    fn empty_parse_is_applied_without_limit() {
+        let changes = remove_all(stored(30), None).unwrap();
+
+        assert_eq!(changes.delta.removed_classes.len(), 30);
+    }
+
+    #[test]
+    fn removals_under_limit_are_applied() {
+        let classes_in_db = stored(4);
+        let kept = classes_in_db[2..]
+            .iter()
+            .map(|class| class.data.clone())
+            .collect();
+
+        let changes = diff_day(day(), classes_in_db, kept, |_| true, Some(LIMIT)).unwrap();
+
+        assert_eq!(changes.delta.removed_classes.len(), 2);
+        assert!(changes.delta.modified_classes.is_empty());
+    }
+
+    #[test]
+    fn removals_over_share_are_held() {
+        let classes_in_db = stored(4);
+        let kept = vec![classes_in_db[0].data.clone()];
+
+        let err = diff_day(day(), classes_in_db, kept, |_| true, Some(LIMIT))
+            .err()
+            .unwrap();
+
+        assert_eq!(err.downcast_ref::<MassRemovalError>().unwrap().removed, 3);
+    }
+
+    #[test]
+    fn removals_over_count_are_held() {
+        // less than half of a big day
+        let classes_in_db = stored(60);
+        let kept = classes_in_db[21..]
+            .iter()
+            .map(|class| class.data.clone())
+            .collect();
+
+        let err = diff_day(day(), classes_in_db, kept, |_| true, Some(LIMIT))
+            .err()
+            .unwrap();
+
+        assert_eq!(err.downcast_ref::<MassRemovalError>().unwrap().removed, 21);
+    }
+
+    #[test]
+    fn only_removable_classes_are_counted() {
+        let mut classes_in_db = stored(3);
+        classes_in_db[0].data.source = "ics".to_owned();
+
+        // only the pjatk classes are the day's own, and all of them are gone
+        let err = diff_day(
+            day(),
+            classes_in_db,
+            Vec::new(),
+            |class| class.source == "pjatk",
+            Some(LIMIT),
+        )
+        .err()
+        .unwrap();
+        assert_eq!(err.downcast_ref::<MassRemovalError>().unwrap().removed, 2);
+    }
+}
@@ -1,13 +1,23 @@
-use std::backtrace::Backtrace;
-
-use aspemu::{ASPEmulator, ASPRequestBuilder, ASPState};
+use std::{backtrace::Backtrace, sync::Arc};
+
+pub use aspemu::transport::{
+    DynamicTransport, LiveTransport, RecordingTransport, ReplayTransport, Transport,
+    TransportConfig,
+};
+pub use aspemu::RetryConfig;
+use aspemu::{ASPEmulator, ASPRequestBuilder, ASPState, RateLimiter};
 use chrono::{NaiveDate, Utc};
 use scraper::{selectable::Selectable, Html, Selector};
 
-use super::types::Class;
+use super::{
+    cache::{self, CacheError, ParseCache},
+    types::QuarantinedClass,
+    LayoutChanged, ParsedDay,
+};
 
 mod aspemu;
-mod deduct;
+pub mod deduct;
+pub mod lecturer;
 
 const GENERAL_SCHEDULE_ENDPOINT: &'static str = "https://planzajec.pjwstk.edu.pl/PlanOgolny3.aspx";
 
@@ -16,14 +26,100 @@ pub type BacktraceFix = std::backtrace::Backtrace;
 pub enum ParseError {
     #[error("HTTP request failed")]
     Http(#[from] reqwest::Error),
-    #[error("PJATK has changed their webpage")]
-    ParsingFailed(BacktraceFix),
+    #[error("PJATK has changed their webpage, '{selector}' didn't match")]
+    ParsingFailed {
+        selector: &'static str,
+        html: String,
+        trace: BacktraceFix,
+    },
 
     #[error("While parsing, body was abrupted")]
     BodyAbrupted(BacktraceFix),
+
+    #[error("Cassette IO failed")]
+    CassetteIo(#[from] std::io::Error),
+    #[error("Cassette contains malformed interaction")]
+    CassetteFormat(#[from] serde_json::Error),
+    #[error("Replayed request doesn't match the cassette")]
+    ReplayMismatch(BacktraceFix),
+
+    #[error("Parse cache failed")]
+    Cache(#[from] CacheError),
+
+    #[error("PJATK responded with {0}")]
+    ServerError(reqwest::StatusCode),
+    #[error("ASP.NET session has expired")]
+    SessionExpired(BacktraceFix),
+    #[error("PJATK is considered down, requests are suspended")]
+    CircuitOpen(BacktraceFix),
+}
+
+impl ParseError {
+    /// Failure might go away if the request is repeated
+    pub fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            ParseError::Http(_)
+                | ParseError::ServerError(_)
+                | ParseError::SessionExpired(_)
+                | ParseError::BodyAbrupted(_)
+        )
+    }
+
+    /// Server state of the session can't be trusted anymore
+    pub fn is_session_lost(&self) -> bool {
+        matches!(
+            self,
+            ParseError::SessionExpired(_) | ParseError::BodyAbrupted(_)
+        )
+    }
+
+    /// Layout failures are reported in parser-agnostic form,
+    /// so the manager can snapshot them
+    pub fn into_report(self) -> eyre::Report {
+        match self {
+            ParseError::ParsingFailed {
+                selector,
+                html,
+                trace,
+            } => eyre::Report::new(LayoutChanged {
+                selector: selector.to_owned(),
+                html,
+                backtrace: trace.to_string(),
+            }),
+            other => eyre::Report::new(other),
+        }
+    }
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct Config {
+    #[serde(default)]
+    pub transport: TransportConfig,
+
+    /// number of independent ASP.NET sessions fetching class details
+    #[serde(default = "default_sessions")]
+    pub sessions: usize,
+    /// minimal delay between any two requests to PJATK, shared by all sessions
+    #[serde(default)]
+    pub min_request_interval: Option<std::time::Duration>,
+
+    #[serde(default)]
+    pub retry: RetryConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            transport: TransportConfig::default(),
+            sessions: default_sessions(),
+            min_request_interval: None,
+            retry: RetryConfig::default(),
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct PjatkClass {
     pub id: String,
     pub name: String,
@@ -40,6 +136,34 @@ pub struct PjatkClass {
     pub is_online: bool,
 }
 
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct PjatkReservation {
+    pub id: String,
+    pub title: String,
+    pub room: String,
+    pub organiser: Option<String>,
+    pub groups: Option<String>,
+    pub from: String,
+    pub to: String,
+    pub date: String,
+}
+
+/// Tooltip of a single schedule cell
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub enum PjatkDetail {
+    Class(PjatkClass),
+    Reservation(PjatkReservation),
+}
+
+impl PjatkDetail {
+    pub fn id(&self) -> &str {
+        match self {
+            PjatkDetail::Class(class) => &class.id,
+            PjatkDetail::Reservation(reservation) => &reservation.id,
+        }
+    }
+}
+
 macro_rules! loc {
     () => {
         std::backtrace::Backtrace::force_capture()
@@ -52,14 +176,19 @@ macro_rules! table_insert (
         }
     );
 macro_rules! hpe {
-    ($e:expr) => {
-        $e.ok_or(ParseError::ParsingFailed(loc!()))?
+    ($e:expr, $selector:expr, $html:expr) => {
+        $e.ok_or_else(|| ParseError::ParsingFailed {
+            selector: $selector,
+            html: $html.to_owned(),
+            trace: loc!(),
+        })?
     };
 }
 
+const RESERVATION_TITLE_SELECTOR: &str = "#ctl06_TytulRezerwacjiLabel";
+
 fn is_reservation(html: &Html) -> bool {
-    const RESERVATION_ID: &str = "#ctl06_TytulRezerwacjiLabel";
-    let reservation_title_selector = scraper::Selector::parse(RESERVATION_ID).unwrap();
+    let reservation_title_selector = scraper::Selector::parse(RESERVATION_TITLE_SELECTOR).unwrap();
 
     html.select(&reservation_title_selector).count() > 0
 }
@@ -68,11 +197,8 @@ fn parse_detail_html(
     class_id: &str,
     fragment: &str,
     code: &str,
-) -> Result<Option<PjatkClass>, ParseError> {
+) -> Result<PjatkDetail, ParseError> {
     let document = scraper::Html::parse_fragment(fragment);
-    if is_reservation(&document) {
-        return Ok(None);
-    }
 
     const NAME_SELECTOR: &str = "#ctl06_NazwaPrzedmiotyLabel";
     const CODE_SELECTOR: &str = "#ctl06_KodPrzedmiotuLabel";
@@ -88,18 +214,47 @@ fn parse_detail_html(
 
     const ONLINE_COLOR_SUBSTR: &str = "background-color:#3AEB34;";
 
+    // shown only for some reservations
+    const ORGANISER_SELECTOR: &str = "#ctl06_OsobaRezerwujacaLabel";
+
     macro_rules! parse_selector {
         ($document:ident, $selector:ident) => {
-            hpe!($document
-                .select(&Selector::parse($selector).unwrap())
-                .next())
+            hpe!(
+                $document
+                    .select(&Selector::parse($selector).unwrap())
+                    .next(),
+                $selector,
+                fragment
+            )
             .text()
             .collect::<String>()
             .trim()
             .to_owned()
         };
     }
-    Ok(Some(PjatkClass {
+
+    let parse_optional = |selector: &str| {
+        document
+            .select(&Selector::parse(selector).unwrap())
+            .next()
+            .map(|element| element.text().collect::<String>().trim().to_owned())
+            .filter(|text| !text.is_empty())
+    };
+
+    if is_reservation(&document) {
+        return Ok(PjatkDetail::Reservation(PjatkReservation {
+            id: class_id.to_owned(),
+            title: parse_selector!(document, RESERVATION_TITLE_SELECTOR),
+            room: parse_selector!(document, ROOM_SELECTOR),
+            organiser: parse_optional(ORGANISER_SELECTOR),
+            groups: parse_optional(GROUPS_SELECTOR),
+            from: parse_selector!(document, FROM_TIME_SELECTORS),
+            to: parse_selector!(document, TO_TIME_SELECTORS),
+            date: parse_selector!(document, DATE_SELECTOR),
+        }));
+    }
+
+    Ok(PjatkDetail::Class(PjatkClass {
         id: class_id.to_owned(),
         name: parse_selector!(document, NAME_SELECTOR),
         code: parse_selector!(document, CODE_SELECTOR),
@@ -158,7 +313,11 @@ fn collect_class_ids(document: &str) -> Result<Vec<(String, String)>, ParseError
         // I'm very sorry for determining is class online by the style, but there is no other
         // source to extract it from
         class_id_style_collected.push((
-            hpe!(class.attr("id").map(String::from)),
+            hpe!(
+                class.attr("id").map(String::from),
+                CLASS_ITEM_SELECTOR,
+                document
+            ),
             class
                 .attr("style")
                 .map(String::from)
@@ -169,113 +328,471 @@ fn collect_class_ids(document: &str) -> Result<Vec<(String, String)>, ParseError
     Ok(class_id_style_collected)
 }
 
+async fn parse_detail(
+    emu: &mut ASPEmulator,
+    class_id: &str,
+    style: &str,
+) -> Result<PjatkDetail, ParseError> {
+    let mut state = ASPState::default();
+
+    table_insert!(
+        state,
+        [
+            "RadScriptManager1",
+            "RadToolTipManager1RTMPanel|RadToolTipManager1RTMPanel"
+        ],
+        [
+            "RadToolTipManager1_ClientState",
+            format!(r#"{{"AjaxTargetControl":"{class_id}","Value":"{class_id}"}}"#)
+        ],
+        ["RadToolTipManager2_ClientState", ""],
+        ["__ASYNCPOST", "true"],
+        ["DataPicker_ClientState", ""] ,
+        ["RadScriptManager1_TSM", ";;System.Web.Extensions, Version=4.0.0.0, Culture=neutral, PublicKeyToken=31bf3856ad364e35:en-US:ceece802-cb39-4409-a6c9-bfa3b2c8bf10:ea597d4b:b25378d2;Telerik.Web.UI, Version=2018.1.117.40, Culture=neutral, PublicKeyToken=121fae78165ba3d4:en-US:3346c3e6-3c4c-4be3-94e3-1928d6a828a1:16e4e7cd:f7645509:ed16cbdc:88144a7a:33715776:24ee1bba:f46195d3:c128760b:874f8ea2:19620875:cda80b3:383e4ce8:1e771326:2003d0b8:aa288e2d:258f1c72:8674cba1:7c926187:b7778d6c:c08e9f8a:a51ee93e:59462f1:6d43f6d9:2bef5fcc:e06b58fd"]
+    );
+
+    let req = ASPRequestBuilder::default()
+        .states_override(state)
+        .kind(aspemu::RequestKind::Event {
+            target: "RadToolTipManager1RTMPanel".into(),
+            argument: Some("undefined".into()),
+        })
+        .is_delta(true)
+        .build()
+        .unwrap();
+
+    let resp = emu.request(req).await?;
+
+    let Some(fragment_html) = resp.body else {
+        return Err(ParseError::BodyAbrupted(Backtrace::capture()));
+    };
+
+    parse_detail_html(class_id, &fragment_html, style)
+}
+
+// brings session to the overview of requested date
+async fn open_day(emu: &mut ASPEmulator, requested_date: NaiveDate) -> Result<String, ParseError> {
+    let req = ASPRequestBuilder::default()
+        .kind(aspemu::RequestKind::Initial)
+        .build()
+        .unwrap();
+
+    let resp = emu.request(req).await?;
+
+    // if not today, then should re-request schedule overview of specified date
+    if requested_date != Utc::now().date_naive() {
+        return pick_date(emu, requested_date).await;
+    }
+
+    resp.body
+        .ok_or(ParseError::BodyAbrupted(Backtrace::capture()))
+}
+
+// switches already opened session to the overview of another date
+async fn pick_date(emu: &mut ASPEmulator, requested_date: NaiveDate) -> Result<String, ParseError> {
+    let state = prepare_date_update_state(&requested_date);
+    let req = ASPRequestBuilder::default()
+        .states_override(state)
+        .is_delta(true)
+        .is_navigation(true)
+        .kind(aspemu::RequestKind::Event {
+            target: "DataPicker".into(),
+            argument: None,
+        })
+        .build()
+        .unwrap();
+
+    emu.request(req)
+        .await?
+        .body
+        .ok_or(ParseError::BodyAbrupted(Backtrace::capture()))
+}
+
+// page is loaded only by sessions which weren't opened yet,
+// the rest just pick another date
+async fn navigate(
+    emu: &mut ASPEmulator,
+    requested_date: NaiveDate,
+    opened: &mut bool,
+) -> Result<String, ParseError> {
+    if *opened {
+        return pick_date(emu, requested_date).await;
+    }
+
+    let overview = open_day(emu, requested_date).await?;
+    *opened = true;
+
+    Ok(overview)
+}
+
+fn default_sessions() -> usize {
+    1
+}
+
+/// Pool of independent ASP.NET sessions, details of one day are
+/// spread between them and fetched concurrently
 pub struct Parser {
-    emu: ASPEmulator,
+    sessions: Vec<ASPEmulator>,
 }
 
 impl Parser {
     pub fn new() -> Self {
+        Self::with_transport(Box::new(LiveTransport::default()))
+    }
+
+    pub fn with_transport(transport: DynamicTransport) -> Self {
         Self {
-            emu: ASPEmulator::new(GENERAL_SCHEDULE_ENDPOINT),
+            sessions: vec![ASPEmulator::new(GENERAL_SCHEDULE_ENDPOINT).with_transport(transport)],
         }
     }
 
-    async fn parse_detail(
-        &mut self,
-        class_id: &str,
-        style: &str,
-    ) -> Result<Option<PjatkClass>, ParseError> {
-        let mut state = ASPState::default();
+    pub fn from_config(config: &Config) -> Result<Self, ParseError> {
+        let limiter = Arc::new(RateLimiter::new(config.min_request_interval));
+        let breaker = config.retry.breaker();
 
-        table_insert!(
-            state,
-            [
-                "RadScriptManager1",
-                "RadToolTipManager1RTMPanel|RadToolTipManager1RTMPanel"
-            ],
-            [
-                "RadToolTipManager1_ClientState",
-                format!(r#"{{"AjaxTargetControl":"{class_id}","Value":"{class_id}"}}"#)
-            ],
-            ["RadToolTipManager2_ClientState", ""],
-            ["__ASYNCPOST", "true"],
-            ["DataPicker_ClientState", ""] ,
-            ["RadScriptManager1_TSM", ";;System.Web.Extensions, Version=4.0.0.0, Culture=neutral, PublicKeyToken=31bf3856ad364e35:en-US:ceece802-cb39-4409-a6c9-bfa3b2c8bf10:ea597d4b:b25378d2;Telerik.Web.UI, Version=2018.1.117.40, Culture=neutral, PublicKeyToken=121fae78165ba3d4:en-US:3346c3e6-3c4c-4be3-94e3-1928d6a828a1:16e4e7cd:f7645509:ed16cbdc:88144a7a:33715776:24ee1bba:f46195d3:c128760b:874f8ea2:19620875:cda80b3:383e4ce8:1e771326:2003d0b8:aa288e2d:258f1c72:8674cba1:7c926187:b7778d6c:c08e9f8a:a51ee93e:59462f1:6d43f6d9:2bef5fcc:e06b58fd"]
-        );
-
-        let req = ASPRequestBuilder::default()
-            .states_override(state)
-            .kind(aspemu::RequestKind::Event {
-                target: "RadToolTipManager1RTMPanel".into(),
-                argument: Some("undefined".into()),
+        let sessions = (0..config.sessions.max(1))
+            .map(|session| {
+                Ok(ASPEmulator::new(GENERAL_SCHEDULE_ENDPOINT)
+                    .with_transport(config.transport.build(session)?)
+                    .with_limiter(limiter.clone())
+                    .with_retry(config.retry.clone(), breaker.clone()))
             })
-            .is_delta(true)
-            .build()
-            .unwrap();
-
-        let resp = self.emu.request(req).await?;
-
-        let Some(fragment_html) = resp.body else {
-            return Err(ParseError::BodyAbrupted(Backtrace::capture()));
-        };
+            .collect::<Result<_, ParseError>>()?;
 
-        parse_detail_html(class_id, &fragment_html, style)
+        Ok(Self { sessions })
     }
 
+    // `opened` tells which sessions already have the schedule page loaded
     async fn parse_day_raw(
         &mut self,
         requested_date: NaiveDate,
-    ) -> Result<Vec<PjatkClass>, ParseError> {
-        let mut classes = Vec::new();
-
-        let req = ASPRequestBuilder::default()
-            .kind(aspemu::RequestKind::Initial)
-            .build()
-            .unwrap();
-
-        let mut resp = self.emu.request(req).await?;
-
-        // if not today, then should re-request schedule overview of specified date
-        if requested_date != Utc::now().date_naive() {
-            let state = prepare_date_update_state(&requested_date);
-            let req = ASPRequestBuilder::default()
-                .states_override(state)
-                .is_delta(true)
-                .kind(aspemu::RequestKind::Event {
-                    target: "DataPicker".into(),
-                    argument: None,
-                })
-                .build()
-                .unwrap();
-            resp = self.emu.request(req).await?;
+        cache: &ParseCache,
+        opened: &mut [bool],
+    ) -> Result<RawDay, ParseError> {
+        let overview = navigate(&mut self.sessions[0], requested_date, &mut opened[0]).await?;
+        let class_id_style_collected = collect_class_ids(&overview)?;
+
+        let day_fingerprint = cache::fingerprint(&class_id_style_collected);
+        if cache
+            .is_day_unchanged(requested_date, &day_fingerprint)
+            .await?
+        {
+            return Ok(RawDay::Unchanged);
         }
 
-        let resp_text = resp
-            .body
-            .ok_or(ParseError::BodyAbrupted(Backtrace::capture()))?;
-        let class_id_style_collected = collect_class_ids(&resp_text)?;
-
-        for class in class_id_style_collected.iter() {
-            if let Some(class) = self.parse_detail(&class.0, &class.1).await? {
-                classes.push(class);
+        // style is the only part of a tooltip the overview shows, other changes
+        // are caught once the entry expires, or right away for near days,
+        // which the manager parses with the cache bypassed
+        let mut details = Vec::with_capacity(class_id_style_collected.len());
+        let mut missing = Vec::new();
+        for (index, (class_id, style)) in class_id_style_collected.iter().enumerate() {
+            let cached = cache
+                .detail::<PjatkDetail>(class_id, &cache::fingerprint(style))
+                .await?;
+
+            if cached.is_none() {
+                missing.push(index);
             }
+            details.push(cached);
+        }
+
+        let workers = self.sessions.len().min(missing.len());
+        let class_ids = &class_id_style_collected;
+        let missing = &missing;
+
+        // work is striped statically, so recorded cassettes replay the same way
+        let fetches = self
+            .sessions
+            .iter_mut()
+            .zip(opened.iter_mut())
+            .take(workers)
+            .enumerate()
+            .map(|(worker, (emu, opened))| async move {
+                if worker != 0 {
+                    navigate(emu, requested_date, opened).await?;
+                }
+
+                let mut fetched = Vec::new();
+                for &index in missing.iter().skip(worker).step_by(workers) {
+                    let (class_id, style) = &class_ids[index];
+                    let detail = parse_detail(emu, class_id, style).await?;
+
+                    cache
+                        .store_detail(class_id, &cache::fingerprint(style), &detail)
+                        .await?;
+                    fetched.push((index, detail));
+                }
+
+                Ok::<_, ParseError>(fetched)
+            });
+
+        for (index, detail) in futures::future::try_join_all(fetches)
+            .await?
+            .into_iter()
+            .flatten()
+        {
+            details[index] = Some(detail);
         }
-        Ok(classes)
+
+        // keeps the same order as the overview
+        let details = details.into_iter().flatten().collect();
+
+        Ok(RawDay::Parsed {
+            details,
+            fingerprint: day_fingerprint,
+        })
     }
 
-    pub async fn parse_day(&mut self, req: NaiveDate) -> Result<Vec<Class>, ParseError> {
-        let raw = self.parse_day_raw(req).await?;
-        Ok(deduct::multi(raw.into_iter()))
+    pub async fn parse_day(
+        &mut self,
+        req: NaiveDate,
+        cache: &ParseCache,
+    ) -> Result<DayOutcome, ParseError> {
+        let mut opened = vec![false; self.sessions.len()];
+        let raw = self.parse_day_raw(req, cache, &mut opened).await?;
+
+        Ok(raw.into())
+    }
+
+    /// Parses days from `start` to `end` inclusive, sessions load the
+    /// schedule page once and only switch dates afterwards
+    pub async fn parse_range(
+        &mut self,
+        start: NaiveDate,
+        end: NaiveDate,
+        cache: &ParseCache,
+    ) -> Result<Vec<(NaiveDate, DayOutcome)>, ParseError> {
+        let mut opened = vec![false; self.sessions.len()];
+
+        let mut days = Vec::new();
+        for day in start.iter_days().take_while(|day| *day <= end) {
+            let raw = self.parse_day_raw(day, cache, &mut opened).await?;
+            days.push((day, raw.into()));
+        }
+
+        Ok(days)
+    }
+}
+
+enum RawDay {
+    Unchanged,
+    Parsed {
+        details: Vec<PjatkDetail>,
+        fingerprint: String,
+    },
+}
+
+pub enum DayOutcome {
+    /// overview is the same as when the day was stored last time
+    Unchanged,
+    Parsed {
+        deducted: deduct::Deducted,
+        fingerprint: String,
+    },
+}
+
+impl From<RawDay> for DayOutcome {
+    fn from(raw: RawDay) -> Self {
+        match raw {
+            RawDay::Unchanged => DayOutcome::Unchanged,
+            RawDay::Parsed {
+                details,
+                fingerprint,
+            } => DayOutcome::Parsed {
+                deducted: deduct::multi(details.into_iter()),
+                fingerprint,
+            },
+        }
     }
 }
 
+fn quarantine(
+    parser: &str,
+    raw: &PjatkDetail,
+    err: &deduct::DeductError,
+) -> eyre::Result<QuarantinedClass> {
+    Ok(QuarantinedClass {
+        parser: parser.to_owned(),
+        source_id: raw.id().strip_suffix(";z").unwrap_or(raw.id()).to_owned(),
+        error: err.to_string(),
+        raw: bson::to_document(raw)?,
+        detected_at: Utc::now(),
+    })
+}
+
+fn into_parsed_day(outcome: DayOutcome) -> eyre::Result<ParsedDay> {
+    let DayOutcome::Parsed {
+        deducted,
+        fingerprint,
+    } = outcome
+    else {
+        return Ok(ParsedDay {
+            unchanged: true,
+            ..Default::default()
+        });
+    };
+
+    let quarantined = deducted
+        .failed
+        .iter()
+        .map(|(raw, err)| quarantine(<Parser as super::ScheduleParser>::NAME, raw, err))
+        .collect::<eyre::Result<_>>()?;
+
+    Ok(ParsedDay {
+        classes: deducted.classes,
+        quarantined,
+        reservations: Some(deducted.reservations),
+        unchanged: false,
+        fingerprint: Some(fingerprint),
+    })
+}
+
 impl super::ScheduleParser for Parser {
     fn parse_day(
         &mut self,
         day: chrono::NaiveDate,
-    ) -> impl std::future::Future<Output = eyre::Result<Vec<Class>>> {
-        async move { self.parse_day(day).await.map_err(eyre::Report::from) }
+        cache: &ParseCache,
+    ) -> impl std::future::Future<Output = eyre::Result<ParsedDay>> {
+        async move {
+            let outcome = self
+                .parse_day(day, cache)
+                .await
+                .map_err(ParseError::into_report)?;
+
+            into_parsed_day(outcome)
+        }
+    }
+
+    async fn parse_range(
+        &mut self,
+        start: chrono::NaiveDate,
+        end: chrono::NaiveDate,
+        cache: &ParseCache,
+    ) -> eyre::Result<Vec<(chrono::NaiveDate, ParsedDay)>> {
+        self.parse_range(start, end, cache)
+            .await
+            .map_err(ParseError::into_report)?
+            .into_iter()
+            .map(|(day, outcome)| Ok((day, into_parsed_day(outcome)?)))
+            .collect()
     }
 
     const NAME: &'static str = "pjatk";
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // session recorded for 2024-10-14: an online lecture, an on-site seminar and a reservation
+    fn replay_config() -> Config {
+        Config {
+            transport: TransportConfig::Replay {
+                cassette: concat!(
+                    env!("CARGO_MANIFEST_DIR"),
+                    "/tests/cassettes/pjatk-2024-10-14"
+                )
+                .into(),
+            },
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn parse_day_replays_recorded_session() {
+        let mut parser = Parser::from_config(&replay_config()).unwrap();
+        let cache = ParseCache::disabled("pjatk");
+
+        let day = NaiveDate::from_ymd_opt(2024, 10, 14).unwrap();
+        let DayOutcome::Parsed { deducted, .. } = parser.parse_day(day, &cache).await.unwrap()
+        else {
+            panic!("disabled cache can't report the day as unchanged");
+        };
+
+        assert!(deducted.failed.is_empty(), "{:?}", deducted.failed);
+
+        let ids: Vec<_> = deducted
+            .classes
+            .iter()
+            .map(|class| class.class_id.as_str())
+            .collect();
+        assert_eq!(ids, ["2451234", "2451235"]);
+
+        let lecture = &deducted.classes[0];
+        assert_eq!(lecture.name, "Podstawy programowania w Javie");
+        assert_eq!(lecture.place, crate::parsing::types::ClassPlace::Online);
+        assert_eq!(lecture.groups.len(), 2);
+        assert_eq!(
+            lecture
+                .range
+                .start
+                .with_timezone(&crate::BOT_TIMEZONE)
+                .to_rfc3339(),
+            "2024-10-14T08:30:00+02:00"
+        );
+
+        let seminar = &deducted.classes[1];
+        assert_eq!(seminar.lecturers[0].first_name, "Anna Maria");
+        assert_eq!(
+            seminar.language,
+            Some(crate::parsing::types::Language::English)
+        );
+
+        assert_eq!(deducted.reservations.len(), 1);
+        assert_eq!(deducted.reservations[0].title, "Spotkanie koła naukowego");
+    }
+
+    // same day recorded with two sessions, the first one fetches the lecture
+    // and the reservation, the second one the seminar in between
+    #[tokio::test]
+    async fn details_fetched_by_several_sessions_keep_overview_order() {
+        let config = Config {
+            sessions: 2,
+            transport: TransportConfig::Replay {
+                cassette: concat!(
+                    env!("CARGO_MANIFEST_DIR"),
+                    "/tests/cassettes/pjatk-2024-10-14-two-sessions"
+                )
+                .into(),
+            },
+            ..Default::default()
+        };
+        let mut parser = Parser::from_config(&config).unwrap();
+        let cache = ParseCache::disabled("pjatk");
+
+        let day = NaiveDate::from_ymd_opt(2024, 10, 14).unwrap();
+        let mut opened = vec![false; 2];
+        let RawDay::Parsed { details, .. } = parser
+            .parse_day_raw(day, &cache, &mut opened)
+            .await
+            .unwrap()
+        else {
+            panic!("disabled cache can't report the day as unchanged");
+        };
+
+        assert_eq!(opened, [true, true]);
+        let ids: Vec<_> = details.iter().map(PjatkDetail::id).collect();
+        assert_eq!(ids, ["2451234;z", "2451235;z", "2451300;z"]);
+
+        let deducted = deduct::multi(details.into_iter());
+        let ids: Vec<_> = deducted
+            .classes
+            .iter()
+            .map(|class| class.class_id.as_str())
+            .collect();
+        assert_eq!(ids, ["2451234", "2451235"]);
+        assert_eq!(deducted.reservations.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn replay_rejects_requests_the_cassette_doesnt_have() {
+        let mut parser = Parser::from_config(&replay_config()).unwrap();
+        let cache = ParseCache::disabled("pjatk");
+
+        // the date picker postback carries another date
+        let day = NaiveDate::from_ymd_opt(2024, 10, 15).unwrap();
+        let err = parser.parse_day(day, &cache).await.err().unwrap();
+
+        assert!(matches!(err, ParseError::ReplayMismatch(_)), "{err:?}");
+    }
+}
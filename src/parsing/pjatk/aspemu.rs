@@ -1,4 +1,4 @@
-use std::{backtrace::Backtrace, borrow::Cow, collections::HashMap};
+use std::{backtrace::Backtrace, borrow::Cow, collections::HashMap, sync::Arc};
 
 use reqwest::{
     header::{HeaderMap, HeaderValue, CONTENT_TYPE, USER_AGENT},
@@ -7,6 +7,11 @@ use reqwest::{
 
 use super::ParseError;
 
+pub mod transport;
+
+use failsafe::futures::CircuitBreaker as _;
+use transport::{DynamicTransport, LiveTransport, TransportRequest, TransportResponse};
+
 pub type MaybeString = Cow<'static, str>;
 
 fn event_headers(is_delta: bool) -> HeaderMap {
@@ -42,7 +47,7 @@ pub enum RequestKind {
     },
 }
 
-#[derive(Debug, derive_builder::Builder)]
+#[derive(Debug, Clone, derive_builder::Builder)]
 #[builder(pattern = "mutable")]
 pub struct ASPRequest {
     #[builder(default)]
@@ -52,6 +57,11 @@ pub struct ASPRequest {
     #[builder(default)]
     is_delta: bool,
 
+    /// event changes what the page shows (e.g. selected date),
+    /// such events are replayed when the session has to be restored
+    #[builder(default)]
+    is_navigation: bool,
+
     #[builder(setter(custom), default)]
     state_override: ASPState,
 }
@@ -88,14 +98,12 @@ pub struct ASPResponse {
 const EVENTTARGET_STATE: &str = "__EVENTTARGET";
 const EVENTARG_STATE: &str = "__EVENTARGUMENT";
 
-async fn process_resp<T: FnOnce(&mut String) -> Result<(), ParseError>>(
-    resp: reqwest::Response,
+fn process_resp<T: FnOnce(&mut String) -> Result<(), ParseError>>(
+    resp: TransportResponse,
     functor: T,
 ) -> Result<ASPResponse, ParseError> {
-    let status = resp.status();
-    let text = resp.text().await?;
-
-    let mut body = Some(text).filter(|t| !t.is_empty());
+    let status = resp.status;
+    let mut body = Some(resp.body).filter(|t| !t.is_empty());
 
     if let Some(ref mut body) = body {
         functor(body)?;
@@ -104,18 +112,154 @@ async fn process_resp<T: FnOnce(&mut String) -> Result<(), ParseError>>(
     Ok(ASPResponse { code: status, body })
 }
 
+// ASP.NET AJAX reports failures inside delta body as `len|error|code|message|`,
+// expired session is usually answered with `pageRedirect` to the start page
+const DELTA_ERROR_MARKERS: [&str; 2] = ["|error|", "|pageRedirect|"];
+
+fn check_response(resp: &TransportResponse, is_delta: bool) -> Result<(), ParseError> {
+    if resp.status.is_server_error() {
+        return Err(ParseError::ServerError(resp.status));
+    }
+
+    if !is_delta {
+        return Ok(());
+    }
+
+    // full page instead of delta means server doesn't know our viewstate anymore
+    let is_delta_body = resp.body.contains('|');
+    let is_error = DELTA_ERROR_MARKERS
+        .iter()
+        .any(|marker| resp.body.contains(marker));
+
+    if !is_delta_body || is_error {
+        return Err(ParseError::SessionExpired(Backtrace::capture()));
+    }
+
+    Ok(())
+}
+
+pub type CircuitBreaker = failsafe::StateMachine<
+    failsafe::failure_policy::ConsecutiveFailures<failsafe::backoff::EqualJittered>,
+    (),
+>;
+
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RetryConfig {
+    /// retries of a single request, on top of the first attempt
+    pub attempts: u32,
+    pub backoff_start: std::time::Duration,
+    pub backoff_max: std::time::Duration,
+
+    /// failed requests in a row after which PJATK is considered down
+    pub breaker_failures: u32,
+    /// how long requests are suspended once the breaker opens
+    pub breaker_cooldown: std::time::Duration,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            attempts: 3,
+            backoff_start: std::time::Duration::from_secs(1),
+            backoff_max: std::time::Duration::from_secs(30),
+            breaker_failures: 5,
+            breaker_cooldown: std::time::Duration::from_secs(60),
+        }
+    }
+}
+
+// failsafe backoffs panic on anything below a second
+fn backoff_bounds(
+    start: std::time::Duration,
+    max: std::time::Duration,
+) -> (std::time::Duration, std::time::Duration) {
+    let start = start.max(std::time::Duration::from_secs(1));
+    (start, max.max(start))
+}
+
+impl RetryConfig {
+    fn backoff(&self) -> failsafe::backoff::EqualJittered {
+        let (start, max) = backoff_bounds(self.backoff_start, self.backoff_max);
+        failsafe::backoff::equal_jittered(start, max)
+    }
+
+    pub fn breaker(&self) -> CircuitBreaker {
+        let (start, max) = backoff_bounds(self.breaker_cooldown, self.breaker_cooldown * 10);
+        let policy = failsafe::failure_policy::consecutive_failures(
+            self.breaker_failures,
+            failsafe::backoff::equal_jittered(start, max),
+        );
+
+        failsafe::Config::new().failure_policy(policy).build()
+    }
+}
+
+/// Keeps requests of all sessions at least `min_interval` apart
+pub struct RateLimiter {
+    min_interval: Option<std::time::Duration>,
+    next_slot: tokio::sync::Mutex<tokio::time::Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(min_interval: Option<std::time::Duration>) -> Self {
+        Self {
+            min_interval,
+            next_slot: tokio::sync::Mutex::new(tokio::time::Instant::now()),
+        }
+    }
+
+    pub async fn acquire(&self) {
+        let Some(min_interval) = self.min_interval else {
+            return;
+        };
+
+        // lock is held while sleeping, so waiters are served one by one
+        let mut next_slot = self.next_slot.lock().await;
+        tokio::time::sleep_until(*next_slot).await;
+        *next_slot = tokio::time::Instant::now() + min_interval;
+    }
+}
+
 #[derive(derive_new::new)]
 pub struct ASPEmulator {
     #[new(default)]
     state: ASPState,
+    #[new(value = "Box::new(LiveTransport::default())")]
+    transport: DynamicTransport,
+    #[new(value = "Arc::new(RateLimiter::new(None))")]
+    limiter: Arc<RateLimiter>,
+    #[new(default)]
+    retry: RetryConfig,
+    #[new(value = "RetryConfig::default().breaker()")]
+    breaker: CircuitBreaker,
+
+    // requests which brought the page to its current view
     #[new(default)]
-    client: reqwest::Client,
+    navigation: Vec<ASPRequest>,
 
     #[new(into)]
     url_base: Cow<'static, str>,
 }
 
 impl ASPEmulator {
+    pub fn with_transport(mut self, transport: DynamicTransport) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    pub fn with_limiter(mut self, limiter: Arc<RateLimiter>) -> Self {
+        self.limiter = limiter;
+        self
+    }
+
+    /// breaker is usually shared between sessions talking to the same site
+    pub fn with_retry(mut self, retry: RetryConfig, breaker: CircuitBreaker) -> Self {
+        self.retry = retry;
+        self.breaker = breaker;
+        self
+    }
+
     fn update_state_from_html(&mut self, text: &str) -> Result<(), ParseError> {
         let body = scraper::Html::parse_document(&text);
 
@@ -152,14 +296,87 @@ impl ASPEmulator {
         Ok(())
     }
 
+    /// Performs request, retrying transient failures with backoff.
+    ///
+    /// If the session was lost, the page is first brought back
+    /// by replaying the initial request and navigation events
     pub async fn request(&mut self, req: ASPRequest) -> Result<ASPResponse, ParseError> {
+        let mut backoff = self.retry.backoff();
+        let mut attempt = 0;
+        let mut restore = false;
+
+        loop {
+            let breaker = self.breaker.clone();
+            let result = breaker
+                .call_with(ParseError::is_transient, self.attempt(&req, restore))
+                .await;
+
+            let err = match result {
+                Ok(resp) => {
+                    self.remember_navigation(req);
+                    return Ok(resp);
+                }
+                Err(failsafe::Error::Rejected) => {
+                    return Err(ParseError::CircuitOpen(Backtrace::capture()))
+                }
+                Err(failsafe::Error::Inner(err)) => err,
+            };
+
+            attempt += 1;
+            if !err.is_transient() || attempt > self.retry.attempts {
+                return Err(err);
+            }
+
+            restore = err.is_session_lost();
+            if let Some(delay) = backoff.next() {
+                tokio::time::sleep(delay).await;
+            }
+        }
+    }
+
+    async fn attempt(
+        &mut self,
+        req: &ASPRequest,
+        restore: bool,
+    ) -> Result<ASPResponse, ParseError> {
+        if restore {
+            for navigation in self.navigation.clone() {
+                self.request_once(navigation).await?;
+            }
+        }
+
+        self.request_once(req.clone()).await
+    }
+
+    fn remember_navigation(&mut self, req: ASPRequest) {
+        match req.kind {
+            RequestKind::Initial => self.navigation = vec![req],
+            // only the latest choice of each control matters, e.g. picked date
+            RequestKind::Event { ref target, .. } if req.is_navigation => {
+                self.navigation.retain(|known| match &known.kind {
+                    RequestKind::Event { target: known, .. } => known != target,
+                    RequestKind::Initial => true,
+                });
+                self.navigation.push(req);
+            }
+            RequestKind::Event { .. } => (),
+        }
+    }
+
+    async fn request_once(&mut self, req: ASPRequest) -> Result<ASPResponse, ParseError> {
         let url = self.url_base.clone().into_owned() + req.endpoint.as_ref();
 
+        self.limiter.acquire().await;
+
         match req.kind {
             RequestKind::Initial => {
-                let resp = self.client.get(url).send().await?;
+                let resp = self.transport.execute(TransportRequest::get(url)).await?;
+                check_response(&resp, false)?;
+
+                // fresh page, nothing from the previous session should leak into it
+                self.state.clear();
 
-                process_resp(resp, |text| self.update_state_from_html(text)).await
+                process_resp(resp, |text| self.update_state_from_html(text))
             }
             RequestKind::Event { target, argument } => {
                 let mut state = self.state.clone();
@@ -176,12 +393,10 @@ impl ASPEmulator {
                 let headers = event_headers(req.is_delta);
 
                 let resp = self
-                    .client
-                    .post(url)
-                    .headers(headers)
-                    .form(&state)
-                    .send()
+                    .transport
+                    .execute(TransportRequest::post(url, &headers, state))
                     .await?;
+                check_response(&resp, req.is_delta)?;
 
                 process_resp(resp, |body| {
                     let lines = body.lines().skip(1);
@@ -199,8 +414,174 @@ impl ASPEmulator {
 
                     return Ok(());
                 })
-                .await
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URL: &str = "https://planzajec.pjwstk.edu.pl/PlanOgolny3.aspx";
+
+    fn retry() -> RetryConfig {
+        RetryConfig {
+            attempts: 5,
+            backoff_start: std::time::Duration::from_secs(1),
+            backoff_max: std::time::Duration::from_secs(1),
+            breaker_failures: 5,
+            breaker_cooldown: std::time::Duration::from_secs(60),
+        }
+    }
+
+    fn initial() -> ASPRequest {
+        ASPRequestBuilder::default()
+            .kind(RequestKind::Initial)
+            .build()
+            .unwrap()
+    }
+
+    // initial page, a picked date and a tooltip of a class on it
+    async fn restore_flow(emu: &mut ASPEmulator) -> Result<ASPResponse, ParseError> {
+        emu.request(initial()).await?;
+
+        let pick_date = ASPRequestBuilder::default()
+            .state_override("DataPicker", "2024-10-14")
+            .is_delta(true)
+            .is_navigation(true)
+            .kind(RequestKind::Event {
+                target: "DataPicker".into(),
+                argument: None,
+            })
+            .build()
+            .unwrap();
+        emu.request(pick_date).await?;
+
+        let tooltip = ASPRequestBuilder::default()
+            .state_override("RadToolTipManager1RTMPanel_ClientState", "2451234;z")
+            .is_delta(true)
+            .kind(RequestKind::Event {
+                target: "RadToolTipManager1RTMPanel".into(),
+                argument: Some("undefined".into()),
+            })
+            .build()
+            .unwrap();
+        emu.request(tooltip).await
+    }
+
+    fn replay(cassette: &str) -> ASPEmulator {
+        let cassette = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/cassettes")
+            .join(cassette)
+            .join("session-0");
+        let transport = transport::ReplayTransport::new(cassette).unwrap();
+
+        let retry = retry();
+        let breaker = retry.breaker();
+        ASPEmulator::new(URL)
+            .with_transport(Box::new(transport))
+            .with_retry(retry, breaker)
+    }
+
+    // cassette has the redirect followed by the initial page and the picked date once again,
+    // any other request would be a replay mismatch
+    #[tokio::test(start_paused = true)]
+    async fn lost_session_is_restored_by_replaying_navigation() {
+        let mut emu = replay("aspemu-session-restore");
+
+        let resp = restore_flow(&mut emu).await.unwrap();
+
+        assert_eq!(
+            resp.body.as_deref(),
+            Some("<div class=\"tooltip\">Bazy danych</div>")
+        );
+        assert_eq!(emu.state["__VIEWSTATE"], "view-5");
+        assert_eq!(emu.navigation.len(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn server_errors_in_a_row_open_the_circuit() {
+        let mut emu = replay("aspemu-circuit-open");
+
+        let err = emu.request(initial()).await.err();
+
+        assert!(matches!(err, Some(ParseError::CircuitOpen(_))), "{err:?}");
+    }
+
+    #[test]
+    fn full_page_in_reply_to_delta_is_expired_session() {
+        let response = |status: u16, body: &str| TransportResponse {
+            status: StatusCode::from_u16(status).unwrap(),
+            body: body.to_owned(),
+        };
+        let page = response(200, "<html><body>Plan zajęć</body></html>");
+
+        assert!(matches!(
+            check_response(&page, true),
+            Err(ParseError::SessionExpired(_))
+        ));
+        assert!(check_response(&page, false).is_ok());
+
+        let redirect = response(200, "1|#||4|\n21|pageRedirect||%2fPlanOgolny3.aspx|");
+        assert!(matches!(
+            check_response(&redirect, true),
+            Err(ParseError::SessionExpired(_))
+        ));
+
+        let delta = response(200, "1|#||4|\n<div/>\n|hiddenField|__VIEWSTATE|view|");
+        assert!(check_response(&delta, true).is_ok());
+
+        assert!(matches!(
+            check_response(&response(503, ""), true),
+            Err(ParseError::ServerError(StatusCode::SERVICE_UNAVAILABLE))
+        ));
+    }
+
+    #[test]
+    fn navigation_keeps_latest_choice_of_each_control() {
+        let mut emu = ASPEmulator::new(URL);
+        let event = |target: &'static str, date: &'static str, is_navigation| {
+            ASPRequestBuilder::default()
+                .state_override(target, date)
+                .is_navigation(is_navigation)
+                .kind(RequestKind::Event {
+                    target: target.into(),
+                    argument: None,
+                })
+                .build()
+                .unwrap()
+        };
+
+        emu.remember_navigation(initial());
+        emu.remember_navigation(event("DataPicker", "2024-10-14", true));
+        emu.remember_navigation(event("RadToolTipManager1RTMPanel", "", false));
+        emu.remember_navigation(event("DataPicker", "2024-10-15", true));
+
+        let picked: Vec<_> = emu
+            .navigation
+            .iter()
+            .map(|req| req.state_override.get("DataPicker").map(String::as_str))
+            .collect();
+        assert_eq!(picked, [None, Some("2024-10-15")]);
+
+        // new initial page starts over
+        emu.remember_navigation(initial());
+        assert_eq!(emu.navigation.len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn limiter_spaces_requests() {
+        let limiter = RateLimiter::new(Some(std::time::Duration::from_secs(2)));
+        let start = tokio::time::Instant::now();
+
+        for _ in 0..3 {
+            limiter.acquire().await;
+        }
+        assert_eq!(start.elapsed(), std::time::Duration::from_secs(4));
+
+        let unlimited = RateLimiter::new(None);
+        unlimited.acquire().await;
+        assert_eq!(start.elapsed(), std::time::Duration::from_secs(4));
+    }
+}
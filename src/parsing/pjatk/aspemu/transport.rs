@@ -0,0 +1,248 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    path::{Path, PathBuf},
+};
+
+use reqwest::{header::HeaderMap, StatusCode};
+use serde::{Deserialize, Serialize};
+
+use crate::parsing::pjatk::ParseError;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Method {
+    Get,
+    Post,
+}
+
+// maps are ordered so recorded cassettes stay stable between runs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransportRequest {
+    pub method: Method,
+    pub url: String,
+    pub headers: BTreeMap<String, String>,
+    pub form: BTreeMap<String, String>,
+}
+
+impl TransportRequest {
+    pub fn get(url: String) -> Self {
+        Self {
+            method: Method::Get,
+            url,
+            headers: BTreeMap::new(),
+            form: BTreeMap::new(),
+        }
+    }
+
+    pub fn post(
+        url: String,
+        headers: &HeaderMap,
+        form: impl IntoIterator<Item = (String, String)>,
+    ) -> Self {
+        let headers = headers
+            .iter()
+            .filter_map(|(name, value)| {
+                value
+                    .to_str()
+                    .ok()
+                    .map(|value| (name.to_string(), value.to_owned()))
+            })
+            .collect();
+
+        Self {
+            method: Method::Post,
+            url,
+            headers,
+            form: form.into_iter().collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransportResponse {
+    #[serde(with = "status_code")]
+    pub status: StatusCode,
+    pub body: String,
+}
+
+mod status_code {
+    use reqwest::StatusCode;
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(status: &StatusCode, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u16(status.as_u16())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<StatusCode, D::Error> {
+        let code = u16::deserialize(deserializer)?;
+        StatusCode::from_u16(code).map_err(D::Error::custom)
+    }
+}
+
+/// Single request/response pair as it is stored in a cassette directory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interaction {
+    pub request: TransportRequest,
+    pub response: TransportResponse,
+}
+
+#[async_trait::async_trait]
+pub trait Transport: Send + Sync {
+    async fn execute(&mut self, req: TransportRequest) -> Result<TransportResponse, ParseError>;
+}
+
+pub type DynamicTransport = Box<dyn Transport>;
+
+pub struct LiveTransport {
+    client: reqwest::Client,
+}
+
+impl Default for LiveTransport {
+    // every transport keeps its own cookie jar, i.e. its own ASP.NET session
+    fn default() -> Self {
+        let client = reqwest::Client::builder()
+            .cookie_store(true)
+            .build()
+            .unwrap_or_default();
+
+        Self { client }
+    }
+}
+
+#[async_trait::async_trait]
+impl Transport for LiveTransport {
+    async fn execute(&mut self, req: TransportRequest) -> Result<TransportResponse, ParseError> {
+        let mut builder = match req.method {
+            Method::Get => self.client.get(req.url),
+            Method::Post => self.client.post(req.url).form(&req.form),
+        };
+
+        for (name, value) in req.headers {
+            builder = builder.header(name, value);
+        }
+
+        let resp = builder.send().await?;
+        let status = resp.status();
+        let body = resp.text().await?;
+
+        Ok(TransportResponse { status, body })
+    }
+}
+
+/// Proxies requests to the live site and writes every interaction
+/// into the cassette directory as `0000.json`, `0001.json`, ...
+pub struct RecordingTransport {
+    inner: LiveTransport,
+    cassette: PathBuf,
+    counter: usize,
+}
+
+impl RecordingTransport {
+    pub fn new(cassette: impl Into<PathBuf>) -> Result<Self, ParseError> {
+        let cassette = cassette.into();
+        std::fs::create_dir_all(&cassette)?;
+
+        Ok(Self {
+            inner: LiveTransport::default(),
+            cassette,
+            counter: 0,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl Transport for RecordingTransport {
+    async fn execute(&mut self, req: TransportRequest) -> Result<TransportResponse, ParseError> {
+        let response = self.inner.execute(req.clone()).await?;
+
+        let interaction = Interaction {
+            request: req,
+            response,
+        };
+
+        let path = self.cassette.join(format!("{:04}.json", self.counter));
+        tokio::fs::write(path, serde_json::to_vec_pretty(&interaction)?).await?;
+        self.counter += 1;
+
+        Ok(interaction.response)
+    }
+}
+
+/// Serves interactions from a cassette in the order they were recorded.
+///
+/// Emulator state is derived only from responses, so a replayed session
+/// produces byte-identical requests; any difference is reported as mismatch
+pub struct ReplayTransport {
+    interactions: VecDeque<Interaction>,
+}
+
+impl ReplayTransport {
+    pub fn new(cassette: impl AsRef<Path>) -> Result<Self, ParseError> {
+        let mut files: Vec<_> = std::fs::read_dir(cassette)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<_, _>>()?;
+
+        files.retain(|path| path.extension().is_some_and(|ext| ext == "json"));
+        files.sort();
+
+        let interactions = files
+            .into_iter()
+            .map(|path| {
+                let content = std::fs::read(path)?;
+                Ok(serde_json::from_slice(&content)?)
+            })
+            .collect::<Result<_, ParseError>>()?;
+
+        Ok(Self { interactions })
+    }
+}
+
+#[async_trait::async_trait]
+impl Transport for ReplayTransport {
+    async fn execute(&mut self, req: TransportRequest) -> Result<TransportResponse, ParseError> {
+        let Some(interaction) = self.interactions.pop_front() else {
+            return Err(ParseError::ReplayMismatch(
+                std::backtrace::Backtrace::capture(),
+            ));
+        };
+
+        if interaction.request != req {
+            return Err(ParseError::ReplayMismatch(
+                std::backtrace::Backtrace::capture(),
+            ));
+        }
+
+        Ok(interaction.response)
+    }
+}
+
+#[derive(serde::Deserialize, Debug, Default, Clone)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum TransportConfig {
+    #[default]
+    Live,
+    Record {
+        cassette: PathBuf,
+    },
+    Replay {
+        cassette: PathBuf,
+    },
+}
+
+impl TransportConfig {
+    /// Each session of the pool gets its own `session-N` directory in the cassette
+    pub fn build(&self, session: usize) -> Result<DynamicTransport, ParseError> {
+        let session_dir = format!("session-{session}");
+
+        let transport: DynamicTransport = match self {
+            TransportConfig::Live => Box::new(LiveTransport::default()),
+            TransportConfig::Record { cassette } => {
+                Box::new(RecordingTransport::new(cassette.join(session_dir))?)
+            }
+            TransportConfig::Replay { cassette } => {
+                Box::new(ReplayTransport::new(cassette.join(session_dir))?)
+            }
+        };
+
+        Ok(transport)
+    }
+}
@@ -1,47 +1,166 @@
-use core::panic;
-
 use chrono::{NaiveDateTime, Utc};
 
-use crate::parsing::types::{Class, ClassKind, ClassPlace, Group, TimeRange};
+use crate::parsing::types::{
+    Class, ClassKind, ClassPlace, Group, Language, Lecturer, Reservation, Room, StudyMode,
+    TimeRange,
+};
+
+use super::{PjatkClass, PjatkDetail, PjatkReservation};
+
+#[derive(thiserror::Error, Debug)]
+pub enum DeductError {
+    #[error("can't parse class date '{0}'")]
+    Date(String, #[source] chrono::ParseError),
+    #[error("can't parse class time '{0}'")]
+    Time(String, #[source] chrono::ParseError),
+    #[error("local time '{0}' doesn't exist in Europe/Warsaw")]
+    NonexistentLocalTime(NaiveDateTime),
+    #[error("class id '{0}' doesn't end with ';z'")]
+    MalformedId(String),
+    #[error("class ends at {end} before it starts at {start}")]
+    InvertedRange {
+        start: chrono::DateTime<Utc>,
+        end: chrono::DateTime<Utc>,
+    },
+}
 
-use super::PjatkClass;
+/// Result of deducting a whole day, failed records are kept raw
+/// so they can be quarantined instead of taking down the parser
+#[derive(Debug, Default)]
+pub struct Deducted {
+    pub classes: Vec<Class>,
+    pub reservations: Vec<Reservation>,
+    pub failed: Vec<(PjatkDetail, DeductError)>,
+}
 
 pub fn deduct_kind(class: &PjatkClass) -> ClassKind {
     match class.kind.as_str() {
         "Wykład" | "Lektorat" => ClassKind::Lecture,
         "Ćwiczenia" | "Internet - ćwiczenia" => ClassKind::Seminar,
         "Projekt dyplomowy" => ClassKind::DiplomaThesis,
-        name => panic!("can't deduct pjatk class kind '{}'", name),
+        name => ClassKind::Other(name.to_owned()),
     }
 }
 
-pub fn deduct_groups(class: &PjatkClass) -> Vec<Group> {
-    let raw_groups = class.groups.split(",");
+fn split_groups(raw_groups: &str) -> Vec<Group> {
     raw_groups
+        .split(",")
         .map(|x| Group {
             code: x.trim().to_owned(),
         })
         .collect()
 }
 
+pub fn deduct_groups(class: &PjatkClass) -> Vec<Group> {
+    split_groups(&class.groups)
+}
+
+// group codes look like "WIs I.3 - 20c ang": faculty with study mode,
+// level.semester, group number and the marker of English track
+fn group_study_mode(group: &Group) -> Option<StudyMode> {
+    let prefix = group.code.split_whitespace().next()?;
+    if !prefix.starts_with('W') || prefix.len() < 3 {
+        return None;
+    }
+
+    match prefix.chars().last()? {
+        's' => Some(StudyMode::OnSite),
+        'n' => Some(StudyMode::PartTime),
+        'i' => Some(StudyMode::Online),
+        _ => None,
+    }
+}
+
+fn group_language(group: &Group) -> Option<Language> {
+    // other codes (e.g. elective lists) don't follow the pattern
+    group_study_mode(group)?;
+
+    match group.code.split_whitespace().last() {
+        Some("ang") => Some(Language::English),
+        _ => Some(Language::Polish),
+    }
+}
+
+/// Mode of the first group which follows PJATK naming
+pub fn deduct_study_mode(groups: &[Group]) -> Option<StudyMode> {
+    groups.iter().find_map(group_study_mode)
+}
+
+/// Known only if all recognized groups agree, joint lectures of
+/// Polish and English tracks are left undetermined
+pub fn deduct_language(groups: &[Group]) -> Option<Language> {
+    let mut languages = groups.iter().filter_map(group_language);
+    let first = languages.next()?;
+
+    languages.all(|language| language == first).then_some(first)
+}
+
 use chrono::TimeZone;
-pub fn deduct_range(class: &PjatkClass) -> TimeRange {
-    let date = chrono::NaiveDate::parse_from_str(&class.date, "%d.%m.%Y").unwrap();
-    let begin_time = chrono::NaiveTime::parse_from_str(&class.from, "%H:%M:%S").unwrap();
-    let end_time = chrono::NaiveTime::parse_from_str(&class.to, "%H:%M:%S").unwrap();
-
-    let datetime_begin = NaiveDateTime::new(date, begin_time);
-    let utc_begin = chrono_tz::Europe::Warsaw
-        .from_local_datetime(&datetime_begin)
-        .unwrap();
-
-    let datetime_end = NaiveDateTime::new(date, end_time);
-    let utc_end = chrono_tz::Europe::Warsaw
-        .from_local_datetime(&datetime_end)
-        .unwrap();
-    TimeRange {
-        start: utc_begin.with_timezone(&Utc),
-        end: utc_end.with_timezone(&Utc),
+fn deduct_time(date: chrono::NaiveDate, time: &str) -> Result<chrono::DateTime<Utc>, DeductError> {
+    let time = chrono::NaiveTime::parse_from_str(time, "%H:%M:%S")
+        .map_err(|err| DeductError::Time(time.to_owned(), err))?;
+
+    let datetime = NaiveDateTime::new(date, time);
+
+    // during DST fall-back the hour repeats, earliest is what PJATK means
+    let local = chrono_tz::Europe::Warsaw
+        .from_local_datetime(&datetime)
+        .earliest()
+        .ok_or(DeductError::NonexistentLocalTime(datetime))?;
+
+    Ok(local.with_timezone(&Utc))
+}
+
+fn parse_range(date: &str, from: &str, to: &str) -> Result<TimeRange, DeductError> {
+    let date = chrono::NaiveDate::parse_from_str(date, "%d.%m.%Y")
+        .map_err(|err| DeductError::Date(date.to_owned(), err))?;
+
+    let (start, end) = (deduct_time(date, from)?, deduct_time(date, to)?);
+
+    // class spanning the repeated hour may get its end from the first pass of it
+    if end <= start {
+        return Err(DeductError::InvertedRange { start, end });
+    }
+
+    Ok(TimeRange { start, end })
+}
+
+pub fn deduct_range(class: &PjatkClass) -> Result<TimeRange, DeductError> {
+    parse_range(&class.date, &class.from, &class.to)
+}
+
+/// PJATK lists lecturers as "Last First, Last First"
+pub fn parse_lecturers(raw: &str) -> Vec<Lecturer> {
+    raw.split(',')
+        .filter_map(|lecturer| {
+            let mut names = lecturer.split_whitespace();
+            let last_name = names.next()?.to_owned();
+
+            Some(Lecturer {
+                first_name: names.collect::<Vec<_>>().join(" "),
+                last_name,
+            })
+        })
+        .collect()
+}
+
+pub fn deduct_lecturers(class: &PjatkClass) -> Vec<Lecturer> {
+    parse_lecturers(&class.lecturer)
+}
+
+/// Rooms are written as "building/number", e.g. "A/157"
+pub fn parse_room(raw: &str) -> Room {
+    let raw = raw.trim();
+
+    let parts = raw
+        .split_once('/')
+        .map(|(building, number)| (building.trim(), number.trim()))
+        .filter(|(building, number)| !building.is_empty() && !number.is_empty());
+
+    Room {
+        building: parts.map(|(building, _)| building.to_owned()),
+        number: parts.map(|(_, number)| number.to_owned()),
+        raw: raw.to_owned(),
     }
 }
 
@@ -50,25 +169,174 @@ pub fn deduct_place(class: &PjatkClass) -> ClassPlace {
         ClassPlace::Online
     } else {
         ClassPlace::OnSite {
-            room: class.room.to_owned(),
+            room: parse_room(&class.room),
         }
     }
 }
 
-pub fn deduct_all(item: PjatkClass) -> Class {
-    // lol, order of call and moves do actually matter here
-    // I wonder why rust can't understand corect order for itself
-    Class {
-        kind: deduct_kind(&item),
-        range: deduct_range(&item),
-        place: deduct_place(&item),
-        groups: deduct_groups(&item),
-        lecturer: item.lecturer,
-        name: item.name,
-        code: item.code,
-        class_id: item.id.strip_suffix(";z").unwrap().to_owned(),
+fn strip_id(id: &str) -> Result<String, DeductError> {
+    id.strip_suffix(";z")
+        .map(String::from)
+        .ok_or_else(|| DeductError::MalformedId(id.to_owned()))
+}
+
+pub fn deduct_id(class: &PjatkClass) -> Result<String, DeductError> {
+    strip_id(&class.id)
+}
+
+pub fn deduct_all(item: &PjatkClass) -> Result<Class, DeductError> {
+    let groups = deduct_groups(item);
+
+    Ok(Class {
+        class_id: deduct_id(item)?,
+        kind: deduct_kind(item),
+        range: deduct_range(item)?,
+        place: deduct_place(item),
+        study_mode: deduct_study_mode(&groups),
+        language: deduct_language(&groups),
+        groups,
+        lecturers: deduct_lecturers(item),
+        name: item.name.clone(),
+        code: item.code.clone(),
+        source: String::new(),
+    })
+}
+
+pub fn deduct_reservation(item: &PjatkReservation) -> Result<Reservation, DeductError> {
+    Ok(Reservation {
+        reservation_id: strip_id(&item.id)?,
+        title: item.title.clone(),
+        room: item.room.clone(),
+        range: parse_range(&item.date, &item.from, &item.to)?,
+        organiser: item.organiser.clone(),
+        groups: item.groups.as_deref().map(split_groups).unwrap_or_default(),
+    })
+}
+
+pub fn multi(input: impl Iterator<Item = PjatkDetail>) -> Deducted {
+    let mut deducted = Deducted::default();
+
+    for item in input {
+        let result = match &item {
+            PjatkDetail::Class(class) => {
+                deduct_all(class).map(|class| deducted.classes.push(class))
+            }
+            PjatkDetail::Reservation(reservation) => deduct_reservation(reservation)
+                .map(|reservation| deducted.reservations.push(reservation)),
+        };
+
+        if let Err(err) = result {
+            deducted.failed.push((item, err));
+        }
     }
+
+    deducted
 }
-pub fn multi(input: impl Iterator<Item = PjatkClass>) -> Vec<Class> {
-    input.map(deduct_all).collect()
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn range_is_taken_in_warsaw_time() {
+        let range = parse_range("14.10.2024", "08:30:00", "10:00:00").unwrap();
+
+        assert_eq!(range.start.to_rfc3339(), "2024-10-14T06:30:00+00:00");
+        assert_eq!(range.end.to_rfc3339(), "2024-10-14T08:00:00+00:00");
+    }
+
+    #[test]
+    fn range_inverted_by_fall_back_is_rejected() {
+        // 02:00-03:00 happens twice on 27.10.2024, both ends resolve to the first pass
+        let err = parse_range("27.10.2024", "02:30:00", "02:15:00").unwrap_err();
+
+        assert!(matches!(err, DeductError::InvertedRange { .. }), "{err:?}");
+    }
+
+    fn groups(codes: &[&str]) -> Vec<Group> {
+        codes
+            .iter()
+            .map(|code| Group {
+                code: (*code).to_owned(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn study_mode_is_taken_from_faculty_prefix() {
+        let mode = |code: &str| group_study_mode(&groups(&[code])[0]);
+
+        assert_eq!(mode("WIs I.3 - 20c"), Some(StudyMode::OnSite));
+        assert_eq!(mode("WIn II.1 - 2w"), Some(StudyMode::PartTime));
+        assert_eq!(mode("WIi I.1 - 1c ang"), Some(StudyMode::Online));
+        assert_eq!(mode("WGs I.3 - 12c"), Some(StudyMode::OnSite));
+
+        for code in ["WIx I.1", "Ws I.1", "ZIs I.1", "Lektorat", ""] {
+            assert_eq!(mode(code), None, "{code}");
+        }
+
+        // first recognized group decides
+        let mixed = groups(&["Lektorat", "WIn I.1 - 1w", "WIs I.1 - 1c"]);
+        assert_eq!(deduct_study_mode(&mixed), Some(StudyMode::PartTime));
+    }
+
+    #[test]
+    fn language_is_known_when_tracks_agree() {
+        assert_eq!(
+            deduct_language(&groups(&["WIs I.1 - 1c ang", "WIs I.1 - 2c ang"])),
+            Some(Language::English)
+        );
+        assert_eq!(
+            deduct_language(&groups(&["WIs I.1 - 1c", "Lektorat ang"])),
+            Some(Language::Polish)
+        );
+
+        // joint lecture of both tracks
+        assert_eq!(
+            deduct_language(&groups(&["WIs I.1 - 1c", "WIs I.1 - 2c ang"])),
+            None
+        );
+        assert_eq!(deduct_language(&groups(&["Lektorat ang"])), None);
+        assert_eq!(deduct_language(&[]), None);
+    }
+
+    #[test]
+    fn lecturers_are_split_into_names() {
+        let lecturers = parse_lecturers("Kalinowski Krzysztof, Wróbel Adrianna Maria");
+
+        assert_eq!(
+            lecturers,
+            [
+                Lecturer {
+                    first_name: "Krzysztof".to_owned(),
+                    last_name: "Kalinowski".to_owned(),
+                },
+                Lecturer {
+                    first_name: "Adrianna Maria".to_owned(),
+                    last_name: "Wróbel".to_owned(),
+                },
+            ]
+        );
+
+        // lone last name, and no lecturer at all
+        let single = parse_lecturers("Nowak");
+        assert_eq!(single[0].full_name(), "Nowak");
+        assert!(parse_lecturers("").is_empty());
+        assert!(parse_lecturers(" , ").is_empty());
+    }
+
+    #[test]
+    fn room_is_split_into_building_and_number() {
+        let room = parse_room(" A/157 ");
+        assert_eq!(room.building.as_deref(), Some("A"));
+        assert_eq!(room.number.as_deref(), Some("157"));
+        assert_eq!(room.raw, "A/157");
+
+        // anything else is only kept as written
+        for raw in ["Aula", "A/", "/157", ""] {
+            let room = parse_room(raw);
+            assert_eq!((room.building, room.number), (None, None), "{raw}");
+            assert_eq!(room.raw, raw);
+        }
+    }
 }
@@ -0,0 +1,285 @@
+use std::{collections::HashMap, sync::Arc};
+
+use chrono::{NaiveDate, Utc};
+
+use crate::parsing::{
+    cache::{self, ParseCache},
+    types::Class,
+    ParsedDay, ScheduleParser,
+};
+
+use super::{
+    aspemu::{self, ASPEmulator, ASPRequestBuilder, ASPState, RateLimiter},
+    collect_class_ids, deduct, parse_detail, prepare_date_update_state, quarantine, ParseError,
+    RawDay, RetryConfig, TransportConfig,
+};
+
+const LECTURER_SCHEDULE_ENDPOINT: &str = "https://planzajec.pjwstk.edu.pl/PlanDydaktyka.aspx";
+
+// combo box with all lecturers, its postback switches the plan to the chosen one
+const LECTURER_SELECTOR_CONTROL: &str = "DydaktycyComboBox";
+
+fn default_endpoint() -> String {
+    LECTURER_SCHEDULE_ENDPOINT.to_owned()
+}
+
+#[derive(serde::Deserialize, Debug)]
+pub struct Config {
+    /// lecturers as PJATK writes them, e.g. "Kalinowski Krzysztof"
+    pub lecturers: Vec<String>,
+
+    #[serde(default = "default_endpoint")]
+    pub endpoint: String,
+    #[serde(default)]
+    pub transport: TransportConfig,
+    #[serde(default)]
+    pub min_request_interval: Option<std::time::Duration>,
+    #[serde(default)]
+    pub retry: RetryConfig,
+}
+
+fn prepare_lecturer_update_state(lecturer: &str) -> ASPState {
+    let client_state = format!(
+        r#"{{"logEntries":[],"value":"{lecturer}","text":"{lecturer}","enabled":true,"checkedIndices":[],"checkedItemsTextOverflows":false}}"#
+    );
+
+    [
+        (
+            "RadScriptManager1",
+            format!("RadAjaxPanel1Panel|{LECTURER_SELECTOR_CONTROL}"),
+        ),
+        ("__EVENTTARGET", LECTURER_SELECTOR_CONTROL.to_owned()),
+        ("__EVENTARGUMENT", String::new()),
+        (LECTURER_SELECTOR_CONTROL, lecturer.to_owned()),
+        ("DydaktycyComboBox_ClientState", client_state),
+        ("__ASYNCPOST", "true".to_owned()),
+        ("RadAJAXControlID", "RadAjaxPanel1".to_owned()),
+    ]
+    .into_iter()
+    .map(|(key, value)| (key.to_owned(), value))
+    .collect()
+}
+
+// brings session to the plan of `lecturer` at requested date
+async fn open_lecturer_day(
+    emu: &mut ASPEmulator,
+    lecturer: &str,
+    requested_date: NaiveDate,
+) -> Result<String, ParseError> {
+    let req = ASPRequestBuilder::default()
+        .kind(aspemu::RequestKind::Initial)
+        .build()
+        .unwrap();
+    emu.request(req).await?;
+
+    let req = ASPRequestBuilder::default()
+        .states_override(prepare_lecturer_update_state(lecturer))
+        .is_delta(true)
+        .is_navigation(true)
+        .kind(aspemu::RequestKind::Event {
+            target: LECTURER_SELECTOR_CONTROL.into(),
+            argument: None,
+        })
+        .build()
+        .unwrap();
+    let mut resp = emu.request(req).await?;
+
+    if requested_date != Utc::now().date_naive() {
+        let req = ASPRequestBuilder::default()
+            .states_override(prepare_date_update_state(&requested_date))
+            .is_delta(true)
+            .is_navigation(true)
+            .kind(aspemu::RequestKind::Event {
+                target: "DataPicker".into(),
+                argument: None,
+            })
+            .build()
+            .unwrap();
+        resp = emu.request(req).await?;
+    }
+
+    resp.body.ok_or(ParseError::BodyAbrupted(
+        std::backtrace::Backtrace::capture(),
+    ))
+}
+
+/// Follows plans of chosen lecturers, so their changes are noticed
+/// sooner than the general plan gets to them
+pub struct LecturerParser {
+    emu: ASPEmulator,
+    lecturers: Vec<String>,
+}
+
+impl LecturerParser {
+    pub fn from_config(config: &Config) -> Result<Self, ParseError> {
+        let limiter = Arc::new(RateLimiter::new(config.min_request_interval));
+
+        let emu = ASPEmulator::new(config.endpoint.clone())
+            .with_transport(config.transport.build(0)?)
+            .with_limiter(limiter)
+            .with_retry(config.retry.clone(), config.retry.breaker());
+
+        Ok(Self {
+            emu,
+            lecturers: config.lecturers.clone(),
+        })
+    }
+
+    // details of all tracked lecturers and fingerprint of their overviews,
+    // details are fetched only once the overviews are known to have changed
+    async fn parse_day_raw(
+        &mut self,
+        requested_date: NaiveDate,
+        cache: &ParseCache,
+    ) -> Result<RawDay, ParseError> {
+        let mut overviews = Vec::with_capacity(self.lecturers.len());
+        for lecturer in &self.lecturers {
+            let overview = open_lecturer_day(&mut self.emu, lecturer, requested_date).await?;
+            overviews.push(collect_class_ids(&overview)?);
+        }
+
+        let fingerprint = cache::fingerprint(&overviews);
+        if cache.is_day_unchanged(requested_date, &fingerprint).await? {
+            return Ok(RawDay::Unchanged);
+        }
+
+        // tooltips are served only for cells of the plan shown, which is
+        // the last lecturer's one now, so lecturers are gone through backwards
+        let mut shown = self.lecturers.len().checked_sub(1);
+        let mut fetched = HashMap::new();
+        for (index, cells) in overviews.iter().enumerate().rev() {
+            for (class_id, style) in cells {
+                // lecturers sharing a class see the same cell
+                if fetched.contains_key(class_id) {
+                    continue;
+                }
+
+                let style_fingerprint = cache::fingerprint(style);
+                let detail = match cache.detail(class_id, &style_fingerprint).await? {
+                    Some(detail) => detail,
+                    None => {
+                        if shown != Some(index) {
+                            let lecturer = &self.lecturers[index];
+                            open_lecturer_day(&mut self.emu, lecturer, requested_date).await?;
+                            shown = Some(index);
+                        }
+
+                        let detail = parse_detail(&mut self.emu, class_id, style).await?;
+                        cache
+                            .store_detail(class_id, &style_fingerprint, &detail)
+                            .await?;
+                        detail
+                    }
+                };
+
+                fetched.insert(class_id.clone(), detail);
+            }
+        }
+
+        // keeps the order of the overviews
+        let details = overviews
+            .iter()
+            .flatten()
+            .filter_map(|(class_id, _)| fetched.remove(class_id))
+            .collect();
+
+        Ok(RawDay::Parsed {
+            details,
+            fingerprint,
+        })
+    }
+}
+
+impl ScheduleParser for LecturerParser {
+    const NAME: &'static str = "pjatk_lecturers";
+    // same classes as in the general plan, just seen from another side
+    const SOURCE: &'static str = <super::Parser as ScheduleParser>::NAME;
+
+    // class missing from lecturer's plan might have just got another lecturer,
+    // so cancellations are left to the general plan
+    fn covers(&self, _class: &Class) -> bool {
+        false
+    }
+
+    async fn parse_day(&mut self, day: NaiveDate, cache: &ParseCache) -> eyre::Result<ParsedDay> {
+        let raw = self
+            .parse_day_raw(day, cache)
+            .await
+            .map_err(ParseError::into_report)?;
+
+        let RawDay::Parsed {
+            details,
+            fingerprint,
+        } = raw
+        else {
+            return Ok(ParsedDay {
+                unchanged: true,
+                ..Default::default()
+            });
+        };
+
+        let deducted = deduct::multi(details.into_iter());
+        let quarantined = deducted
+            .failed
+            .iter()
+            .map(|(raw, err)| quarantine(Self::NAME, raw, err))
+            .collect::<eyre::Result<_>>()?;
+
+        // reservations are left to the general plan
+        Ok(ParsedDay {
+            classes: deducted.classes,
+            quarantined,
+            reservations: None,
+            unchanged: false,
+            fingerprint: Some(fingerprint),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // session recorded for 2024-10-14 of a lecturer with an online lecture
+    // and a class shared with another lecturer
+    fn replay_config() -> Config {
+        Config {
+            lecturers: vec!["Kowalski Jan".to_owned()],
+            endpoint: default_endpoint(),
+            transport: TransportConfig::Replay {
+                cassette: concat!(
+                    env!("CARGO_MANIFEST_DIR"),
+                    "/tests/cassettes/pjatk-lecturers-2024-10-14"
+                )
+                .into(),
+            },
+            min_request_interval: None,
+            retry: RetryConfig::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn parse_day_replays_recorded_session() {
+        let mut parser = LecturerParser::from_config(&replay_config()).unwrap();
+        let cache = ParseCache::disabled(LecturerParser::NAME);
+
+        let day = NaiveDate::from_ymd_opt(2024, 10, 14).unwrap();
+        let parsed = parser.parse_day(day, &cache).await.unwrap();
+
+        assert!(!parsed.unchanged);
+        assert!(parsed.quarantined.is_empty());
+        assert!(parsed.reservations.is_none());
+
+        let ids: Vec<_> = parsed
+            .classes
+            .iter()
+            .map(|class| class.class_id.as_str())
+            .collect();
+        assert_eq!(ids, ["2451234", "2451240"]);
+
+        let exercise = &parsed.classes[1];
+        assert_eq!(exercise.name, "Programowanie obiektowe w Javie");
+        assert_eq!(exercise.lecturers.len(), 2);
+        assert_eq!(exercise.lecturers[1].last_name, "Zieliński");
+    }
+}
@@ -0,0 +1,136 @@
+use std::{collections::HashSet, convert::Infallible};
+
+use chrono::NaiveDate;
+use slog::Logger;
+
+use crate::{channels, notifications::UpdateEvents};
+
+use super::{
+    control::{ControlRequest, Controls},
+    ics::{self, IcsParser},
+    manager::{self, ClassDelta, ParserManager},
+    pjatk::{self, lecturer::LecturerParser},
+    ScheduleParser,
+};
+
+/// Parser implementation of a `[[parsers]]` section with its own settings
+#[derive(serde::Deserialize, Debug)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Kind {
+    Pjatk(pjatk::Config),
+    PjatkLecturers(pjatk::lecturer::Config),
+    Ics(ics::Config),
+}
+
+/// One `[[parsers]]` section, each one is run by its own [`ParserManager`]
+#[derive(serde::Deserialize, Debug)]
+pub struct Entry {
+    #[serde(flatten)]
+    pub manager: manager::Config,
+    #[serde(flatten)]
+    pub kind: Kind,
+}
+
+impl Entry {
+    pub fn name(&self) -> &str {
+        let default = match self.kind {
+            Kind::Pjatk(_) => pjatk::Parser::NAME,
+            Kind::PjatkLecturers(_) => LecturerParser::NAME,
+            Kind::Ics(_) => IcsParser::NAME,
+        };
+
+        self.manager.name.as_deref().unwrap_or(default)
+    }
+
+    pub fn start(
+        &'static self,
+        db: &mongodb::Database,
+        logger: &Logger,
+        events_consumer: impl channels::Tx<UpdateEvents>,
+        control: impl channels::Rx<ControlRequest>,
+    ) -> eyre::Result<tokio::task::JoinHandle<eyre::Result<Infallible>>> {
+        let handle = match &self.kind {
+            Kind::Pjatk(config) => {
+                let parser = pjatk::Parser::from_config(config)?;
+                ParserManager::new(db, parser, &self.manager, logger).work(events_consumer, control)
+            }
+            Kind::PjatkLecturers(config) => {
+                let parser = LecturerParser::from_config(config)?;
+                ParserManager::new(db, parser, &self.manager, logger).work(events_consumer, control)
+            }
+            Kind::Ics(config) => {
+                let parser = IcsParser::from_config(config);
+                ParserManager::new(db, parser, &self.manager, logger).work(events_consumer, control)
+            }
+        };
+
+        Ok(handle)
+    }
+
+    /// Parses the range once, outside of the parsing loop, see [`ParserManager::backfill`]
+    pub async fn backfill(
+        &'static self,
+        db: &mongodb::Database,
+        logger: &Logger,
+        start: NaiveDate,
+        end: NaiveDate,
+        dry_run: bool,
+        allow_mass_removal: bool,
+    ) -> eyre::Result<(usize, ClassDelta)> {
+        match &self.kind {
+            Kind::Pjatk(config) => {
+                let parser = pjatk::Parser::from_config(config)?;
+                ParserManager::new(db, parser, &self.manager, logger)
+                    .with_dry_run(dry_run)
+                    .backfill(start, end, allow_mass_removal)
+                    .await
+            }
+            Kind::PjatkLecturers(config) => {
+                let parser = LecturerParser::from_config(config)?;
+                ParserManager::new(db, parser, &self.manager, logger)
+                    .with_dry_run(dry_run)
+                    .backfill(start, end, allow_mass_removal)
+                    .await
+            }
+            Kind::Ics(config) => {
+                let parser = IcsParser::from_config(config);
+                ParserManager::new(db, parser, &self.manager, logger)
+                    .with_dry_run(dry_run)
+                    .backfill(start, end, allow_mass_removal)
+                    .await
+            }
+        }
+    }
+}
+
+/// Starts every configured parser, names must be unique since
+/// parsing progress and cache are kept per name
+pub fn start_all(
+    entries: &'static [Entry],
+    db: &mongodb::Database,
+    logger: &Logger,
+    events_consumer: impl channels::Tx<UpdateEvents> + Clone,
+) -> eyre::Result<(
+    Vec<tokio::task::JoinHandle<eyre::Result<Infallible>>>,
+    Controls,
+)> {
+    let mut names = HashSet::new();
+    for entry in entries {
+        if !names.insert(entry.name()) {
+            eyre::bail!(
+                "parser '{}' is configured twice, give it a distinct `name`",
+                entry.name()
+            );
+        }
+    }
+
+    let mut handles = Vec::new();
+    let mut controls = Controls::new();
+    for entry in entries {
+        let (control_tx, control_rx) = kanal::unbounded_async();
+        handles.push(entry.start(db, logger, events_consumer.clone(), control_rx)?);
+        controls.insert(entry.name(), control_tx);
+    }
+
+    Ok((handles, controls))
+}
@@ -17,6 +17,9 @@ pub enum ClassKind {
     Seminar,
     #[strum(serialize = "diploma_thesis")]
     DiplomaThesis,
+    /// Kind which isn't known yet, holds the raw name from the source
+    #[strum(default)]
+    Other(String),
 }
 
 use chrono::{DateTime, Utc};
@@ -32,14 +35,44 @@ pub struct TimeRange {
     pub end: chrono::DateTime<Utc>,
 }
 
-#[derive(Debug, Hash, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+#[derive(
+    Debug,
+    Hash,
+    serde::Serialize,
+    serde::Deserialize,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    strum::Display,
+    strum::EnumString,
+)]
 pub enum StudyMode {
+    #[strum(serialize = "online")]
     Online,
+    #[strum(serialize = "onsite")]
     OnSite,
+    #[strum(serialize = "part_time")]
     PartTime,
 }
+
+/// Language the class is taught in
+#[derive(
+    Debug,
+    Hash,
+    serde::Serialize,
+    serde::Deserialize,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    strum::Display,
+    strum::EnumString,
+)]
 pub enum Language {
+    #[strum(serialize = "english")]
     English,
+    #[strum(serialize = "polish")]
     Polish,
 }
 #[derive(Debug, Hash, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
@@ -48,11 +81,34 @@ pub struct Group {
     pub code: String,
 }
 
+#[derive(Debug, Hash, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct Lecturer {
+    pub first_name: String,
+    pub last_name: String,
+}
+
+impl Lecturer {
+    pub fn full_name(&self) -> String {
+        format!("{} {}", self.first_name, self.last_name)
+            .trim()
+            .to_owned()
+    }
+}
+
+/// Room as written by the source (e.g. "A/157"),
+/// building and number are missing if it doesn't follow the usual form
+#[derive(Debug, Hash, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct Room {
+    pub building: Option<String>,
+    pub number: Option<String>,
+    pub raw: String,
+}
+
 #[derive(Debug, Hash, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
 #[serde(tag = "type")]
 pub enum ClassPlace {
     Online,
-    OnSite { room: String },
+    OnSite { room: Room },
 }
 
 #[derive(Debug, Hash, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
@@ -61,12 +117,317 @@ pub struct Class {
     pub name: String,
     pub code: String,
     pub kind: ClassKind,
-    pub lecturer: String,
+    pub lecturers: Vec<Lecturer>,
     pub range: TimeRange,
     pub place: ClassPlace,
     pub groups: Vec<Group>,
+
+    /// `None` when the source doesn't tell
+    #[serde(default)]
+    pub study_mode: Option<StudyMode>,
+    #[serde(default)]
+    pub language: Option<Language>,
+
+    /// Source the class was stored from, filled in by [`ParserManager`].
+    /// Parsers only cancel classes of their own source
+    ///
+    /// [`ParserManager`]: super::manager::ParserManager
+    #[serde(default)]
+    pub source: String,
 }
 
 impl Model for Class {
     const COLLECTION_NAME: &'static str = "classes";
 }
+
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    Hash,
+    serde::Serialize,
+    serde::Deserialize,
+    strum::Display,
+    strum::IntoStaticStr,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ClassField {
+    /// name or subject code, the code names the same subject in short,
+    /// so a new one means another subject just like a new name does
+    #[strum(serialize = "name")]
+    Name,
+    #[strum(serialize = "kind")]
+    Kind,
+    #[strum(serialize = "lecturer")]
+    Lecturer,
+    #[strum(serialize = "time")]
+    Time,
+    #[strum(serialize = "place")]
+    Place,
+    #[strum(serialize = "groups")]
+    Groups,
+}
+
+impl Class {
+    /// Fields which differ between two versions of the same class,
+    /// study mode and language aren't told to users, so they are left out
+    pub fn diff(&self, other: &Class) -> Vec<ClassField> {
+        let mut changed = Vec::new();
+
+        if self.name != other.name || self.code != other.code {
+            changed.push(ClassField::Name);
+        }
+        if self.kind != other.kind {
+            changed.push(ClassField::Kind);
+        }
+        if self.lecturers != other.lecturers {
+            changed.push(ClassField::Lecturer);
+        }
+        if self.range != other.range {
+            changed.push(ClassField::Time);
+        }
+        if self.place != other.place {
+            changed.push(ClassField::Place);
+        }
+        if self.groups != other.groups {
+            changed.push(ClassField::Groups);
+        }
+
+        changed
+    }
+}
+
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize, strum::Display,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Modified,
+}
+
+/// What a parser did to a stored class, kept after the class itself is gone
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
+pub struct ClassChange {
+    pub kind: ChangeKind,
+    pub class_id: String,
+    /// groups of both versions, so users who left the class find the change too
+    pub groups: Vec<Group>,
+    pub before: Option<Class>,
+    pub after: Option<Class>,
+    #[serde(default)]
+    pub diff: Vec<ClassField>,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub changed_at: DateTime<Utc>,
+}
+
+impl Model for ClassChange {
+    const COLLECTION_NAME: &'static str = "class_changes";
+}
+
+impl ClassChange {
+    fn new(
+        kind: ChangeKind,
+        class_id: String,
+        before: Option<Class>,
+        after: Option<Class>,
+        diff: Vec<ClassField>,
+    ) -> Self {
+        let mut groups: Vec<Group> = Vec::new();
+        for group in before
+            .iter()
+            .chain(after.iter())
+            .flat_map(|class| &class.groups)
+        {
+            if !groups.contains(group) {
+                groups.push(group.clone());
+            }
+        }
+
+        Self {
+            kind,
+            class_id,
+            groups,
+            before,
+            after,
+            diff,
+            changed_at: Utc::now(),
+        }
+    }
+
+    pub fn added(class: Class) -> Self {
+        let class_id = class.class_id.clone();
+        Self::new(ChangeKind::Added, class_id, None, Some(class), Vec::new())
+    }
+
+    pub fn removed(class: Class) -> Self {
+        let class_id = class.class_id.clone();
+        Self::new(ChangeKind::Removed, class_id, Some(class), None, Vec::new())
+    }
+
+    pub fn modified(before: Class, after: Class, diff: Vec<ClassField>) -> Self {
+        let class_id = after.class_id.clone();
+        Self::new(
+            ChangeKind::Modified,
+            class_id,
+            Some(before),
+            Some(after),
+            diff,
+        )
+    }
+
+    /// The class as it is now, or as it was before removal
+    pub fn class(&self) -> Option<&Class> {
+        self.after.as_ref().or(self.before.as_ref())
+    }
+}
+
+/// Room booking or university event (defence, open day, etc.),
+/// it occupies a room but isn't a class of any subject
+#[derive(Debug, Hash, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct Reservation {
+    pub reservation_id: String,
+    pub title: String,
+    pub room: String,
+    pub range: TimeRange,
+    pub organiser: Option<String>,
+    /// groups mentioned by the reservation, usually empty
+    pub groups: Vec<Group>,
+}
+
+impl Model for Reservation {
+    const COLLECTION_NAME: &'static str = "reservations";
+}
+
+/// Raw source record which couldn't be turned into a [`Class`]
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone)]
+pub struct QuarantinedClass {
+    pub parser: String,
+    pub source_id: String,
+    pub error: String,
+    pub raw: bson::Document,
+    #[serde(with = "chrono_datetime_as_bson_datetime")]
+    pub detected_at: DateTime<Utc>,
+}
+
+impl Model for QuarantinedClass {
+    const COLLECTION_NAME: &'static str = "quarantined_classes";
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn class() -> Class {
+        let start = Utc.with_ymd_and_hms(2024, 10, 14, 8, 0, 0).unwrap();
+
+        Class {
+            class_id: "2451234".to_owned(),
+            name: "Bazy danych".to_owned(),
+            code: "BYT".to_owned(),
+            kind: ClassKind::Lecture,
+            lecturers: vec![Lecturer {
+                first_name: "Jan".to_owned(),
+                last_name: "Kowalski".to_owned(),
+            }],
+            range: TimeRange {
+                start,
+                end: start + chrono::TimeDelta::minutes(90),
+            },
+            place: ClassPlace::OnSite {
+                room: Room {
+                    building: Some("A".to_owned()),
+                    number: Some("157".to_owned()),
+                    raw: "A/157".to_owned(),
+                },
+            },
+            groups: vec![Group {
+                code: "WIs I.1 - 1c".to_owned(),
+            }],
+            study_mode: Some(StudyMode::OnSite),
+            language: Some(Language::Polish),
+            source: "pjatk".to_owned(),
+        }
+    }
+
+    fn diff(change: impl FnOnce(&mut Class)) -> Vec<ClassField> {
+        let mut changed = class();
+        change(&mut changed);
+        class().diff(&changed)
+    }
+
+    #[test]
+    fn same_class_has_no_diff() {
+        assert!(diff(|_| ()).is_empty());
+    }
+
+    #[test]
+    fn each_field_is_told_apart() {
+        assert_eq!(
+            diff(|class| class.name = "Systemy operacyjne".to_owned()),
+            [ClassField::Name]
+        );
+        assert_eq!(
+            diff(|class| class.code = "SOP".to_owned()),
+            [ClassField::Name]
+        );
+        assert_eq!(
+            diff(|class| class.kind = ClassKind::Seminar),
+            [ClassField::Kind]
+        );
+        assert_eq!(
+            diff(|class| class.lecturers[0].last_name = "Nowak".to_owned()),
+            [ClassField::Lecturer]
+        );
+        assert_eq!(
+            diff(|class| class.lecturers.clear()),
+            [ClassField::Lecturer]
+        );
+        assert_eq!(
+            diff(|class| class.range.end += chrono::TimeDelta::minutes(15)),
+            [ClassField::Time]
+        );
+        assert_eq!(
+            diff(|class| class.place = ClassPlace::Online),
+            [ClassField::Place]
+        );
+        assert_eq!(
+            diff(|class| class.groups.push(Group {
+                code: "WIs I.1 - 2c".to_owned(),
+            })),
+            [ClassField::Groups]
+        );
+    }
+
+    #[test]
+    fn fields_users_are_not_told_about_are_ignored() {
+        let changed = diff(|class| {
+            class.study_mode = None;
+            class.language = Some(Language::English);
+            class.source = "ics".to_owned();
+            class.class_id = "other".to_owned();
+        });
+
+        assert!(changed.is_empty());
+    }
+
+    #[test]
+    fn several_fields_are_listed_in_order() {
+        let changed = diff(|class| {
+            class.place = ClassPlace::Online;
+            class.range.start += chrono::TimeDelta::hours(1);
+            class.name = "Systemy operacyjne".to_owned();
+        });
+
+        assert_eq!(
+            changed,
+            [ClassField::Name, ClassField::Time, ClassField::Place]
+        );
+    }
+}